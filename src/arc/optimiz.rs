@@ -4,7 +4,6 @@ use std::{
   ops::Deref,
   ptr::NonNull,
   sync::atomic::{AtomicUsize, Ordering, fence},
-  usize,
 };
 
 struct ArcData<T> {
@@ -116,7 +115,7 @@ impl<T> Drop for Arc<T> {
 }
 
 impl<T> Weak<T> {
-  pub fn data(&self) -> &ArcData<T> {
+  fn data(&self) -> &ArcData<T> {
     unsafe { self.ptr.as_ref() }
   }
 
@@ -72,14 +72,14 @@ impl<T> Drop for Arc<T> {
     if self.weak.data().ref_count.fetch_sub(1, Ordering::Release) == 1 {
       fence(Ordering::Acquire);
       unsafe {
-        std::mem::swap(&mut (*self.weak.data().data.get()), &mut None);
+        *self.weak.data().data.get() = None;
       }
     }
   }
 }
 
 impl<T> Weak<T> {
-  pub fn data(&self) -> &ArcData<T> {
+  fn data(&self) -> &ArcData<T> {
     unsafe { self.ptr.as_ref() }
   }
 
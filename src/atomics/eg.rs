@@ -122,14 +122,14 @@ pub fn lazy_init() {
 
 pub fn allocate_new_id() {
   static NEXT_ID: AtomicU64 = AtomicU64::new(0);
-  let next = NEXT_ID.load(Relaxed);
+  let _next = NEXT_ID.load(Relaxed);
   NEXT_ID
     .fetch_update(Relaxed, Relaxed, |id| id.checked_add(1))
     .expect("To many Ids!");
 }
 pub fn lazy_onetime_init() -> u64 {
   static KEY: AtomicU64 = AtomicU64::new(0);
-  let mut x = KEY.load(Relaxed);
+  let x = KEY.load(Relaxed);
   if x == 0 {
     let new_key = rand::rng().random(); // generate new key randomly
     match KEY.compare_exchange(0, new_key, Relaxed, Relaxed) {
@@ -1,4 +1,7 @@
+mod async_one_shot;
 mod avoid_brrow;
+mod bounded;
 mod mutex_chan;
 mod one_shot;
 mod safety;
+mod unbounded;
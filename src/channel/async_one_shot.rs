@@ -0,0 +1,365 @@
+use std::{
+  cell::UnsafeCell,
+  error::Error,
+  fmt,
+  future::Future,
+  mem::MaybeUninit,
+  pin::{Pin, pin},
+  sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+  },
+  task::{Context, Poll, Wake, Waker},
+  thread::{self, Thread},
+};
+
+// Nobody is touching the waker.
+const IDLE: usize = 0;
+// `register` is replacing the waker.
+const REGISTERING: usize = 1;
+// `take` is taking the waker out. Set on top of REGISTERING, it tells
+// `register` to wake the new waker itself.
+const WAKING: usize = 2;
+
+// A slot for a single task's Waker, after the one in `futures`: one side
+// registers, the other wakes, without a lock, and without missing a wake
+// up that races with a registration.
+struct AtomicWaker {
+  state: AtomicUsize,
+  waker: UnsafeCell<Option<Waker>>,
+}
+
+unsafe impl Sync for AtomicWaker {}
+
+impl AtomicWaker {
+  const fn new() -> Self {
+    AtomicWaker {
+      state: AtomicUsize::new(IDLE),
+      waker: UnsafeCell::new(None),
+    }
+  }
+
+  // Only ever called by one task at a time.
+  fn register(&self, waker: &Waker) {
+    match self
+      .state
+      .compare_exchange(IDLE, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+      .unwrap_or_else(|s| s)
+    {
+      IDLE => {
+        let slot = unsafe { &mut *self.waker.get() };
+        if !slot.as_ref().is_some_and(|w| w.will_wake(waker)) {
+          *slot = Some(waker.clone());
+        }
+        // Release: `take` finds the new waker.
+        if self
+          .state
+          .compare_exchange(REGISTERING, IDLE, Ordering::AcqRel, Ordering::Acquire)
+          .is_err()
+        {
+          // `take` came by in the meantime, and left the waker to us.
+          let waker = slot.take().unwrap();
+          self.state.swap(IDLE, Ordering::AcqRel);
+          waker.wake();
+        }
+      }
+      // A wake up is underway, maybe too late for what the caller saw
+      // before registering: have it poll again.
+      _ => waker.wake_by_ref(),
+    }
+  }
+
+  fn take(&self) -> Option<Waker> {
+    match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+      IDLE => {
+        let waker = unsafe { (*self.waker.get()).take() };
+        self.state.fetch_and(!WAKING, Ordering::Release);
+        waker
+      }
+      // `register` wakes, or another `take` already does.
+      _ => None,
+    }
+  }
+
+  fn wake(&self) {
+    if let Some(waker) = self.take() {
+      waker.wake();
+    }
+  }
+}
+
+struct Channel<T> {
+  message: UnsafeCell<MaybeUninit<T>>,
+  ready: AtomicBool,
+  // Set when the `Sender` is dropped, sent or not.
+  sender_gone: AtomicBool,
+  // Set when the `Receiver` is dropped or closed.
+  receiver_gone: AtomicBool,
+  // The task awaiting the `Receiver`.
+  rx_task: AtomicWaker,
+  // The task in `Sender::poll_closed`.
+  tx_task: AtomicWaker,
+}
+
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+pub struct Sender<T> {
+  chan: Arc<Channel<T>>,
+}
+
+// Resolves to the message, or to `Canceled` if the sender went away
+// without sending.
+pub struct Receiver<T> {
+  chan: Arc<Channel<T>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    "oneshot canceled".fmt(f)
+  }
+}
+
+impl Error for Canceled {}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+  let chan = Arc::new(Channel {
+    message: UnsafeCell::new(MaybeUninit::uninit()),
+    ready: AtomicBool::new(false),
+    sender_gone: AtomicBool::new(false),
+    receiver_gone: AtomicBool::new(false),
+    rx_task: AtomicWaker::new(),
+    tx_task: AtomicWaker::new(),
+  });
+  (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+impl<T> Sender<T> {
+  // Gives the message back if the receiver is gone.
+  pub fn send(self, msg: T) -> Result<(), T> {
+    if self.is_canceled() {
+      return Err(msg);
+    }
+    unsafe { (*self.chan.message.get()).write(msg) };
+    self.chan.ready.store(true, Ordering::Release);
+    // Dropping `self` wakes the receiver.
+    Ok(())
+  }
+
+  // Whether the receiver was dropped or closed.
+  pub fn is_canceled(&self) -> bool {
+    self.chan.receiver_gone.load(Ordering::Relaxed)
+  }
+
+  // Ready once the receiver is dropped or closed, to stop working on a
+  // message nobody wants anymore.
+  pub fn poll_closed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+    if self.chan.receiver_gone.load(Ordering::Relaxed) {
+      return Poll::Ready(());
+    }
+    self.chan.tx_task.register(cx.waker());
+    // Again, in case it was set before we registered.
+    if self.chan.receiver_gone.load(Ordering::Relaxed) {
+      return Poll::Ready(());
+    }
+    Poll::Pending
+  }
+}
+
+impl<T> Drop for Sender<T> {
+  fn drop(&mut self) {
+    self.chan.sender_gone.store(true, Ordering::Release);
+    self.chan.rx_task.wake();
+  }
+}
+
+impl<T> Receiver<T> {
+  // Makes `send` fail from now on, and wakes `poll_closed`. A message
+  // that's already sent can still be received.
+  pub fn close(&mut self) {
+    self.chan.receiver_gone.store(true, Ordering::Relaxed);
+    self.chan.tx_task.wake();
+  }
+
+  // Doesn't register a waker.
+  pub fn try_recv(&mut self) -> Poll<Result<T, Canceled>> {
+    // Looked at first, as a sender may send right before going away.
+    let sender_gone = self.chan.sender_gone.load(Ordering::Acquire);
+    if self.chan.ready.swap(false, Ordering::Acquire) {
+      return Poll::Ready(Ok(unsafe { (*self.chan.message.get()).assume_init_read() }));
+    }
+    if sender_gone {
+      return Poll::Ready(Err(Canceled));
+    }
+    Poll::Pending
+  }
+}
+
+impl<T> Future for Receiver<T> {
+  type Output = Result<T, Canceled>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    if let Poll::Ready(r) = this.try_recv() {
+      return Poll::Ready(r);
+    }
+    this.chan.rx_task.register(cx.waker());
+    // Again, in case the sender left before we registered.
+    this.try_recv()
+  }
+}
+
+impl<T> Drop for Receiver<T> {
+  fn drop(&mut self) {
+    self.close();
+  }
+}
+
+impl<T> Drop for Channel<T> {
+  fn drop(&mut self) {
+    if *self.ready.get_mut() {
+      unsafe { self.message.get_mut().assume_init_drop() };
+    }
+  }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+  fn wake(self: Arc<Self>) {
+    self.0.unpark();
+  }
+
+  fn wake_by_ref(self: &Arc<Self>) {
+    self.0.unpark();
+  }
+}
+
+// Runs a future to completion on the current thread, parking it while the
+// future is pending. Just enough to use async code without a runtime.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+  let mut fut = pin!(fut);
+  let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+  let mut cx = Context::from_waker(&waker);
+  loop {
+    match fut.as_mut().poll(&mut cx) {
+      Poll::Ready(out) => return out,
+      // Wakes up spuriously at times, and then just polls again.
+      Poll::Pending => thread::park(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{future, sync::atomic::AtomicU32, time::Duration};
+
+  #[test]
+  fn test_channel() {
+    let (sender, receiver) = channel();
+    sender.send("hello").unwrap();
+    assert_eq!(block_on(receiver), Ok("hello"));
+
+    thread::scope(|s| {
+      let (sender, receiver) = channel();
+      s.spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        sender.send(1).unwrap();
+      });
+      assert_eq!(block_on(receiver), Ok(1));
+    });
+  }
+
+  #[test]
+  fn test_canceled() {
+    let (sender, mut receiver) = channel::<i32>();
+    assert_eq!(receiver.try_recv(), Poll::Pending);
+    drop(sender);
+    assert_eq!(receiver.try_recv(), Poll::Ready(Err(Canceled)));
+
+    thread::scope(|s| {
+      let (sender, receiver) = channel::<i32>();
+      s.spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        drop(sender);
+      });
+      assert_eq!(block_on(receiver), Err(Canceled));
+    });
+  }
+
+  #[test]
+  fn test_poll_closed() {
+    let (mut sender, receiver) = channel::<i32>();
+    thread::scope(|s| {
+      s.spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        drop(receiver);
+      });
+      block_on(future::poll_fn(|cx| sender.poll_closed(cx)));
+    });
+    assert!(sender.is_canceled());
+    assert_eq!(sender.send(1), Err(1));
+
+    // A closed receiver still gets what was sent before.
+    let (sender, mut receiver) = channel();
+    sender.send(2).unwrap();
+    receiver.close();
+    assert_eq!(block_on(receiver), Ok(2));
+  }
+
+  #[test]
+  fn test_drop() {
+    let msg = Arc::new(());
+    let (sender, receiver) = channel();
+    sender.send(msg.clone()).unwrap();
+    assert_eq!(Arc::strong_count(&msg), 2);
+    drop(receiver);
+    assert_eq!(Arc::strong_count(&msg), 1);
+  }
+
+  struct CountingWaker(AtomicU32);
+
+  impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+      self.0.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  #[test]
+  fn test_atomic_waker() {
+    let slot = AtomicWaker::new();
+    let count = Arc::new(CountingWaker(AtomicU32::new(0)));
+    let waker = Waker::from(count.clone());
+    slot.wake();
+    slot.register(&waker);
+    slot.register(&waker);
+    slot.wake();
+    // Taken out by the first wake.
+    slot.wake();
+    assert_eq!(count.0.load(Ordering::Relaxed), 1);
+
+    // Registering during a wake has the new waker woken right away.
+    slot.state.store(WAKING, Ordering::Relaxed);
+    slot.register(&waker);
+    assert_eq!(count.0.load(Ordering::Relaxed), 2);
+  }
+
+  #[test]
+  fn test_many() {
+    thread::scope(|s| {
+      let receivers: Vec<_> = (0..100)
+        .map(|i| {
+          let (sender, receiver) = channel();
+          s.spawn(move || sender.send(i).unwrap());
+          receiver
+        })
+        .collect();
+      for (i, receiver) in receivers.into_iter().enumerate() {
+        assert_eq!(block_on(receiver), Ok(i));
+      }
+    });
+  }
+}
@@ -1,6 +1,5 @@
 use std::{
   cell::UnsafeCell,
-  iter::Rev,
   marker::PhantomData,
   mem::MaybeUninit,
   sync::atomic::{AtomicBool, Ordering},
@@ -22,7 +21,7 @@ impl<T> Channel<T> {
     }
   }
 
-  pub fn split(&mut self) -> (Sender<T>, Receiver<T>) {
+  pub fn split(&mut self) -> (Sender<'_, T>, Receiver<'_, T>) {
     *self = Self::new();
     (
       Sender {
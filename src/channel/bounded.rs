@@ -0,0 +1,597 @@
+use std::{
+  cell::UnsafeCell,
+  error::Error,
+  fmt,
+  mem::MaybeUninit,
+  ops::Deref,
+  sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering, fence},
+  time::{Duration, Instant},
+};
+
+use atomic_wait::{wake_all, wake_one};
+
+use crate::lock::backoff::{Backoff, SpinPolicy};
+use crate::lock::futex;
+
+struct Slot<T> {
+  // `2 * pos` while free for position `pos`, and `2 * pos + 1` once the
+  // message for it is in. Taking it out frees it for `pos + cap`. (Not
+  // Vyukov's `pos + 1` and `pos + cap`, which can't tell full from free
+  // with a capacity of 1.)
+  stamp: AtomicUsize,
+  msg: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Keeps the head and tail, written by the two sides, off each other's
+// cache line.
+#[repr(align(64))]
+pub(super) struct Padded<T>(pub(super) T);
+
+impl<T> Deref for Padded<T> {
+  type Target = T;
+  fn deref(&self) -> &T {
+    &self.0
+  }
+}
+
+// A bounded multi-producer multi-consumer queue, after Dmitry Vyukov's:
+// a ring buffer where every slot carries a stamp saying whose turn it is,
+// so senders and receivers only contend on `tail` and `head` respectively.
+// Blocked senders and receivers sleep on a futex that the other side bumps
+// (only if somebody sleeps) after making progress.
+//
+// Once closed, sending fails, and receiving fails as soon as the messages
+// still in the channel are taken. A send racing with `close` may still get
+// its message in; if nobody takes it, it's dropped along with the channel.
+pub struct Channel<T> {
+  buffer: Box<[Slot<T>]>,
+  // The next position to receive from.
+  head: Padded<AtomicUsize>,
+  // The next position to send to.
+  tail: Padded<AtomicUsize>,
+  // Bumped after a receive, for senders waiting for room.
+  received: AtomicU32,
+  send_waiters: AtomicU32,
+  // Bumped after a send, for receivers waiting for a message.
+  sent: AtomicU32,
+  recv_waiters: AtomicU32,
+  closed: AtomicBool,
+}
+
+// The channel is closed. Gives the message back.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+  Full(T),
+  Disconnected(T),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SendTimeoutError<T> {
+  Timeout(T),
+  Disconnected(T),
+}
+
+// The channel is closed and empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+  Empty,
+  Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+  Timeout,
+  Disconnected,
+}
+
+// Like std's, without requiring `T: Debug`.
+impl<T> fmt::Debug for SendError<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SendError").finish_non_exhaustive()
+  }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TrySendError::Full(_) => "Full(..)".fmt(f),
+      TrySendError::Disconnected(_) => "Disconnected(..)".fmt(f),
+    }
+  }
+}
+
+impl<T> fmt::Debug for SendTimeoutError<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SendTimeoutError::Timeout(_) => "Timeout(..)".fmt(f),
+      SendTimeoutError::Disconnected(_) => "Disconnected(..)".fmt(f),
+    }
+  }
+}
+
+impl<T> fmt::Display for SendError<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    "sending on a closed channel".fmt(f)
+  }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TrySendError::Full(_) => "sending on a full channel".fmt(f),
+      TrySendError::Disconnected(_) => "sending on a closed channel".fmt(f),
+    }
+  }
+}
+
+impl<T> fmt::Display for SendTimeoutError<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SendTimeoutError::Timeout(_) => "timed out waiting on a channel".fmt(f),
+      SendTimeoutError::Disconnected(_) => "sending on a closed channel".fmt(f),
+    }
+  }
+}
+
+impl fmt::Display for RecvError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    "receiving on a closed channel".fmt(f)
+  }
+}
+
+impl fmt::Display for TryRecvError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TryRecvError::Empty => "receiving on an empty channel".fmt(f),
+      TryRecvError::Disconnected => RecvError.fmt(f),
+    }
+  }
+}
+
+impl fmt::Display for RecvTimeoutError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RecvTimeoutError::Timeout => "timed out waiting on a channel".fmt(f),
+      RecvTimeoutError::Disconnected => RecvError.fmt(f),
+    }
+  }
+}
+
+impl<T> Error for SendError<T> {}
+impl<T> Error for TrySendError<T> {}
+impl<T> Error for SendTimeoutError<T> {}
+impl Error for RecvError {}
+impl Error for TryRecvError {}
+impl Error for RecvTimeoutError {}
+
+unsafe impl<T: Send> Sync for Channel<T> {}
+
+impl<T> Channel<T> {
+  pub fn new(cap: usize) -> Self {
+    assert!(cap > 0, "capacity must be non-zero");
+    Self {
+      buffer: (0..cap)
+        .map(|i| Slot {
+          stamp: AtomicUsize::new(2 * i),
+          msg: UnsafeCell::new(MaybeUninit::uninit()),
+        })
+        .collect(),
+      head: Padded(AtomicUsize::new(0)),
+      tail: Padded(AtomicUsize::new(0)),
+      received: AtomicU32::new(0),
+      send_waiters: AtomicU32::new(0),
+      sent: AtomicU32::new(0),
+      recv_waiters: AtomicU32::new(0),
+      closed: AtomicBool::new(false),
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.buffer.len()
+  }
+
+  // Makes sending fail from now on, and receiving once the channel is
+  // empty, waking everyone blocked. Returns false if already closed.
+  pub fn close(&self) -> bool {
+    if self.closed.swap(true, Ordering::SeqCst) {
+      return false;
+    }
+    // A waiter loads the futex before looking at `closed` one last time:
+    // either it sees the bump, or it sees `closed`.
+    for futex in [&self.sent, &self.received] {
+      futex.fetch_add(1, Ordering::SeqCst);
+      wake_all(futex);
+    }
+    true
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::Relaxed)
+  }
+
+  pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+    if self.closed.load(Ordering::Acquire) {
+      return Err(TrySendError::Disconnected(msg));
+    }
+    let cap = self.buffer.len();
+    let mut pos = self.tail.load(Ordering::Relaxed);
+    loop {
+      let slot = &self.buffer[pos % cap];
+      let stamp = slot.stamp.load(Ordering::Acquire);
+      let diff = stamp.wrapping_sub(pos.wrapping_mul(2)) as isize;
+      if diff == 0 {
+        // The slot is free for this position: claim it.
+        match self.tail.compare_exchange_weak(
+          pos,
+          pos.wrapping_add(1),
+          Ordering::Relaxed,
+          Ordering::Relaxed,
+        ) {
+          Ok(_) => {
+            unsafe { (*slot.msg.get()).write(msg) };
+            slot
+              .stamp
+              .store(pos.wrapping_mul(2).wrapping_add(1), Ordering::Release);
+            notify(&self.sent, &self.recv_waiters);
+            return Ok(());
+          }
+          Err(p) => pos = p,
+        }
+      } else if diff < 0 {
+        // The message from a lap ago is still there.
+        return Err(TrySendError::Full(msg));
+      } else {
+        // Another sender claimed it first.
+        pos = self.tail.load(Ordering::Relaxed);
+      }
+    }
+  }
+
+  pub fn try_recv(&self) -> Result<T, TryRecvError> {
+    match self.pop() {
+      Some(msg) => Ok(msg),
+      None if !self.closed.load(Ordering::Acquire) => Err(TryRecvError::Empty),
+      // One last look, for what was sent right before closing.
+      None => self.pop().ok_or(TryRecvError::Disconnected),
+    }
+  }
+
+  fn pop(&self) -> Option<T> {
+    let cap = self.buffer.len();
+    let mut pos = self.head.load(Ordering::Relaxed);
+    loop {
+      let slot = &self.buffer[pos % cap];
+      let stamp = slot.stamp.load(Ordering::Acquire);
+      let diff = stamp.wrapping_sub(pos.wrapping_mul(2).wrapping_add(1)) as isize;
+      if diff == 0 {
+        match self.head.compare_exchange_weak(
+          pos,
+          pos.wrapping_add(1),
+          Ordering::Relaxed,
+          Ordering::Relaxed,
+        ) {
+          Ok(_) => {
+            let msg = unsafe { (*slot.msg.get()).assume_init_read() };
+            slot
+              .stamp
+              .store(pos.wrapping_add(cap).wrapping_mul(2), Ordering::Release);
+            notify(&self.received, &self.send_waiters);
+            return Some(msg);
+          }
+          Err(p) => pos = p,
+        }
+      } else if diff < 0 {
+        // Nothing sent to this position yet.
+        return None;
+      } else {
+        pos = self.head.load(Ordering::Relaxed);
+      }
+    }
+  }
+
+  pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+    self.send_deadline(msg, None).map_err(|e| match e {
+      SendTimeoutError::Disconnected(msg) => SendError(msg),
+      SendTimeoutError::Timeout(_) => unreachable!("no deadline"),
+    })
+  }
+
+  pub fn send_timeout(&self, msg: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+    self.send_deadline(msg, Instant::now().checked_add(timeout))
+  }
+
+  fn send_deadline(&self, msg: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+    let mut msg = Some(msg);
+    let mut result = Ok(());
+    let done = block(&self.received, &self.send_waiters, deadline, || match self
+      .try_send(msg.take().unwrap())
+    {
+      Ok(()) => true,
+      Err(TrySendError::Full(m)) => {
+        msg = Some(m);
+        false
+      }
+      Err(TrySendError::Disconnected(m)) => {
+        result = Err(SendTimeoutError::Disconnected(m));
+        true
+      }
+    });
+    if done {
+      result
+    } else {
+      Err(SendTimeoutError::Timeout(msg.unwrap()))
+    }
+  }
+
+  pub fn recv(&self) -> Result<T, RecvError> {
+    self.recv_deadline(None).map_err(|_| RecvError)
+  }
+
+  pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+    self.recv_deadline(Instant::now().checked_add(timeout))
+  }
+
+  fn recv_deadline(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+    let mut result = Err(RecvTimeoutError::Timeout);
+    block(&self.sent, &self.recv_waiters, deadline, || {
+      match self.try_recv() {
+        Ok(msg) => result = Ok(msg),
+        Err(TryRecvError::Empty) => return false,
+        Err(TryRecvError::Disconnected) => result = Err(RecvTimeoutError::Disconnected),
+      }
+      true
+    });
+    result
+  }
+}
+
+// Called after making progress: wakes one thread waiting for it, if any.
+fn notify(futex: &AtomicU32, waiters: &AtomicU32) {
+  // Pairs with the fence in `block`: either we see the waiter, or it sees
+  // what we did when it tries one last time before going to sleep.
+  fence(Ordering::SeqCst);
+  if waiters.load(Ordering::Relaxed) != 0 {
+    futex.fetch_add(1, Ordering::Release);
+    wake_one(futex);
+  }
+}
+
+// Retries `attempt` until it's done, one way or another, sleeping on
+// `futex` in between. Returns false if `deadline` passed first.
+fn block(
+  futex: &AtomicU32,
+  waiters: &AtomicU32,
+  deadline: Option<Instant>,
+  mut attempt: impl FnMut() -> bool,
+) -> bool {
+  // Spinning, then yielding, gives the other side a chance to catch up
+  // before we bother the kernel.
+  let policy = SpinPolicy::exponential();
+  let mut backoff = Backoff::new(&policy);
+  loop {
+    if attempt() {
+      return true;
+    }
+    if deadline.is_some_and(|d| Instant::now() >= d) {
+      return false;
+    }
+    if backoff.spin() {
+      continue;
+    }
+    waiters.fetch_add(1, Ordering::Relaxed);
+    fence(Ordering::SeqCst);
+    let seen = futex.load(Ordering::Acquire);
+    let done = attempt();
+    if !done {
+      // A timed out wait didn't take a wake up meant for someone else,
+      // and after a real one we try again before looking at the clock.
+      futex::wait_deadline(futex, seen, deadline);
+    }
+    waiters.fetch_sub(1, Ordering::Relaxed);
+    if done {
+      return true;
+    }
+  }
+}
+
+impl<T> Drop for Channel<T> {
+  fn drop(&mut self) {
+    let cap = self.buffer.len();
+    let head = *self.head.0.get_mut();
+    let tail = *self.tail.0.get_mut();
+    let mut pos = head;
+    while pos != tail {
+      unsafe { (*self.buffer[pos % cap].msg.get()).assume_init_drop() };
+      pos = pos.wrapping_add(1);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::channel::mutex_chan;
+  use std::{sync::Arc, sync::mpsc, thread};
+
+  #[test]
+  fn test_channel() {
+    let chan = Channel::new(2);
+    assert_eq!(chan.try_recv(), Err(TryRecvError::Empty));
+    chan.send(1).unwrap();
+    assert_eq!(chan.try_send(2), Ok(()));
+    assert_eq!(chan.try_send(3), Err(TrySendError::Full(3)));
+    assert_eq!(
+      chan.send_timeout(3, Duration::from_millis(10)),
+      Err(SendTimeoutError::Timeout(3))
+    );
+    assert_eq!(chan.recv(), Ok(1));
+    assert_eq!(chan.try_recv(), Ok(2));
+    assert_eq!(
+      chan.recv_timeout(Duration::from_millis(10)),
+      Err(RecvTimeoutError::Timeout)
+    );
+    // Around the ring a few times.
+    for i in 0..10 {
+      chan.send(i).unwrap();
+      assert_eq!(chan.recv(), Ok(i));
+    }
+  }
+
+  #[test]
+  fn test_blocking() {
+    let chan = Channel::new(1);
+    thread::scope(|s| {
+      s.spawn(|| {
+        for i in 0..1000 {
+          chan.send(i).unwrap();
+        }
+      });
+      for i in 0..1000 {
+        assert_eq!(chan.recv(), Ok(i));
+      }
+    });
+    thread::scope(|s| {
+      s.spawn(|| {
+        thread::sleep(Duration::from_millis(50));
+        chan.send(1).unwrap();
+      });
+      assert_eq!(chan.recv_timeout(Duration::from_secs(10)), Ok(1));
+    });
+  }
+
+  #[test]
+  fn test_close() {
+    let chan = Channel::new(2);
+    chan.send(1).unwrap();
+    assert!(chan.close());
+    assert!(!chan.close());
+    assert!(chan.is_closed());
+    assert_eq!(chan.try_send(2), Err(TrySendError::Disconnected(2)));
+    assert_eq!(chan.send(2), Err(SendError(2)));
+    // What's in there can still be received.
+    assert_eq!(chan.recv(), Ok(1));
+    assert_eq!(chan.recv(), Err(RecvError));
+    assert_eq!(chan.try_recv(), Err(TryRecvError::Disconnected));
+    assert_eq!(
+      chan.recv_timeout(Duration::from_millis(10)),
+      Err(RecvTimeoutError::Disconnected)
+    );
+
+    // Wakes up blocked receivers and senders.
+    let empty = Channel::<i32>::new(1);
+    let full = Channel::new(1);
+    full.send(0).unwrap();
+    thread::scope(|s| {
+      let receiver = s.spawn(|| empty.recv());
+      let sender = s.spawn(|| full.send(1));
+      thread::sleep(Duration::from_millis(50));
+      empty.close();
+      full.close();
+      assert_eq!(receiver.join().unwrap(), Err(RecvError));
+      assert_eq!(sender.join().unwrap(), Err(SendError(1)));
+    });
+  }
+
+  #[test]
+  fn test_mpmc() {
+    let chan = Channel::new(16);
+    let total: u64 = thread::scope(|s| {
+      for p in 0..4 {
+        let chan = &chan;
+        s.spawn(move || {
+          for i in 0..10_000 {
+            chan.send(p * 10_000 + i).unwrap();
+          }
+        });
+      }
+      let consumers: Vec<_> = (0..4)
+        .map(|_| s.spawn(|| (0..10_000).map(|_| chan.recv().unwrap()).sum::<u64>()))
+        .collect();
+      consumers.into_iter().map(|c| c.join().unwrap()).sum()
+    });
+    assert_eq!(total, (0..40_000).sum());
+  }
+
+  #[test]
+  fn test_drop() {
+    let msg = Arc::new(());
+    let chan = Channel::new(4);
+    for _ in 0..3 {
+      chan.send(msg.clone()).unwrap();
+    }
+    chan.recv().unwrap();
+    assert_eq!(Arc::strong_count(&msg), 3);
+    drop(chan);
+    assert_eq!(Arc::strong_count(&msg), 1);
+  }
+
+  const MESSAGES: usize = 200_000;
+  const CAP: usize = 1024;
+
+  fn bench(producers: usize) {
+    let per_producer = MESSAGES / producers;
+
+    let chan = Channel::new(CAP);
+    let start = Instant::now();
+    thread::scope(|s| {
+      for _ in 0..producers {
+        s.spawn(|| {
+          for i in 0..per_producer {
+            chan.send(i).unwrap();
+          }
+        });
+      }
+      for _ in 0..per_producer * producers {
+        std::hint::black_box(chan.recv().unwrap());
+      }
+    });
+    let bounded = start.elapsed();
+
+    let (tx, rx) = mpsc::sync_channel(CAP);
+    let start = Instant::now();
+    thread::scope(|s| {
+      for _ in 0..producers {
+        let tx = tx.clone();
+        s.spawn(move || {
+          for i in 0..per_producer {
+            tx.send(i).unwrap();
+          }
+        });
+      }
+      for _ in 0..per_producer * producers {
+        std::hint::black_box(rx.recv().unwrap());
+      }
+    });
+    let std_mpsc = start.elapsed();
+
+    let chan = mutex_chan::Channel::new();
+    let start = Instant::now();
+    thread::scope(|s| {
+      for _ in 0..producers {
+        s.spawn(|| {
+          for i in 0..per_producer {
+            chan.send(i);
+          }
+        });
+      }
+      for _ in 0..per_producer * producers {
+        std::hint::black_box(chan.receive());
+      }
+    });
+    let mutex_chan = start.elapsed();
+
+    dbg!(producers, bounded, std_mpsc, mutex_chan);
+  }
+
+  #[test]
+  fn bounded_channel_benchmark() {
+    bench(1);
+    bench(4);
+  }
+}
@@ -1,105 +1,447 @@
 use std::{
   cell::UnsafeCell,
+  error::Error,
+  fmt,
   mem::MaybeUninit,
   sync::{
-    Condvar, Mutex,
-    atomic::{AtomicBool, AtomicPtr, Ordering},
+    Arc,
+    atomic::{AtomicU8, Ordering},
   },
+  thread::{self, Thread},
+  time::{Duration, Instant},
 };
 
-#[derive(Debug, PartialEq, Eq)]
-enum State {
-  Empty,
-  Ready,
-  Writing,
-  Reading,
-}
+use crate::lock::backoff::{Backoff, SpinPolicy};
 
-pub struct Channel<T> {
+// Nothing sent yet.
+const EMPTY: u8 = 0;
+// The sender is writing the message.
+const WRITING: u8 = 1;
+// The message is there.
+const READY: u8 = 2;
+// The receiver took the message.
+const TAKEN: u8 = 3;
+// The receiver closed the channel, or went away, before a message arrived.
+const CLOSED: u8 = 4;
+// The sender went away without sending.
+const DISCONNECTED: u8 = 5;
+// Added to EMPTY or WRITING while the receiver is parked, with its thread
+// in `waiter`.
+const WAITING: u8 = 8;
+
+struct Channel<T> {
   msg: UnsafeCell<MaybeUninit<T>>,
-  state: AtomicPtr<State>,
+  state: AtomicU8,
+  // Only written by the receiver while WAITING is not set, and only taken
+  // by the sender after clearing it.
+  waiter: UnsafeCell<Option<Thread>>,
 }
 
 unsafe impl<T: Send> Sync for Channel<T> {}
 
-impl<T> Channel<T> {
-  pub fn new() -> Self {
-    Channel {
-      msg: UnsafeCell::new(MaybeUninit::uninit()),
-      state: AtomicPtr::new(unsafe { &State::Empty as *const State as *mut State }),
+pub struct Sender<T> {
+  chan: Arc<Channel<T>>,
+}
+
+pub struct Receiver<T> {
+  chan: Arc<Channel<T>>,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+  let chan = Arc::new(Channel {
+    msg: UnsafeCell::new(MaybeUninit::uninit()),
+    state: AtomicU8::new(EMPTY),
+    waiter: UnsafeCell::new(None),
+  });
+  (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+// No message, and there never will be one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+  Empty,
+  Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+  Timeout,
+  Disconnected,
+}
+
+impl fmt::Display for RecvError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    "receiving on a closed channel".fmt(f)
+  }
+}
+
+impl fmt::Display for TryRecvError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TryRecvError::Empty => "receiving on an empty channel".fmt(f),
+      TryRecvError::Disconnected => RecvError.fmt(f),
+    }
+  }
+}
+
+impl fmt::Display for RecvTimeoutError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RecvTimeoutError::Timeout => "timed out waiting on a channel".fmt(f),
+      RecvTimeoutError::Disconnected => RecvError.fmt(f),
+    }
+  }
+}
+
+impl Error for RecvError {}
+impl Error for TryRecvError {}
+impl Error for RecvTimeoutError {}
+
+impl<T> Sender<T> {
+  // Gives the message back if the receiver closed the channel or is gone.
+  pub fn send(self, msg: T) -> Result<(), T> {
+    if !self.start_writing() {
+      return Err(msg);
     }
+    unsafe { (*self.chan.msg.get()).write(msg) };
+    self.finish_writing()
   }
 
-  pub fn send(&self, val: T) {
-    unsafe {
-      if *self.state.swap(
-        unsafe { &State::Writing as *const State as *mut State },
+  // EMPTY -> WRITING, keeping WAITING.
+  fn start_writing(&self) -> bool {
+    let mut s = self.chan.state.load(Ordering::Relaxed);
+    loop {
+      if s & !WAITING != EMPTY {
+        return false;
+      }
+      match self.chan.state.compare_exchange_weak(
+        s,
+        WRITING | (s & WAITING),
+        Ordering::Relaxed,
         Ordering::Relaxed,
-      ) != State::Empty
+      ) {
+        Ok(_) => return true,
+        Err(prev) => s = prev,
+      }
+    }
+  }
+
+  // WRITING -> READY, or takes the message back if the receiver went away
+  // in the meantime.
+  fn finish_writing(&self) -> Result<(), T> {
+    let mut s = self.chan.state.load(Ordering::Relaxed);
+    loop {
+      if s == CLOSED {
+        return Err(unsafe { (*self.chan.msg.get()).assume_init_read() });
+      }
+      // Acquire to see the receiver's `waiter`.
+      match self
+        .chan
+        .state
+        .compare_exchange_weak(s, READY, Ordering::AcqRel, Ordering::Relaxed)
       {
-        panic!("Channel is not ready to send a message");
+        Ok(_) => {
+          self.wake(s);
+          return Ok(());
+        }
+        Err(prev) => s = prev,
       }
     }
+  }
 
-    unsafe { (*self.msg.get()).write(val) };
-    self.state.store(
-      unsafe { &State::Ready as *const State as *mut State },
-      Ordering::Release,
-    );
+  // After leaving state `s`, unparks the receiver if it was waiting.
+  fn wake(&self, s: u8) {
+    if s & WAITING != 0
+      && let Some(t) = unsafe { (*self.chan.waiter.get()).take() }
+    {
+      t.unpark();
+    }
   }
 
-  pub fn is_ready(&self) -> bool {
-    unsafe { *self.state.load(Ordering::Acquire) == State::Ready }
+  // Whether the receiver closed the channel or went away.
+  pub fn is_closed(&self) -> bool {
+    self.chan.state.load(Ordering::Relaxed) == CLOSED
   }
+}
 
-  pub fn receive(&self) -> T {
-    if self
-      .state
-      .compare_exchange(
-        unsafe { &State::Ready as *const State as *mut State },
-        unsafe { &State::Reading as *const State as *mut State },
+impl<T> Drop for Sender<T> {
+  fn drop(&mut self) {
+    // Nothing to do if we sent, or the receiver is gone.
+    let mut s = self.chan.state.load(Ordering::Relaxed);
+    while s & !WAITING == EMPTY {
+      match self.chan.state.compare_exchange_weak(
+        s,
+        DISCONNECTED,
         Ordering::Acquire,
         Ordering::Relaxed,
-      )
-      .is_err()
-    {
-      panic!("Channel is not ready to receive a message");
+      ) {
+        Ok(_) => {
+          self.wake(s);
+          return;
+        }
+        Err(prev) => s = prev,
+      }
+    }
+  }
+}
+
+impl<T> Receiver<T> {
+  pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+    match self.chan.state.load(Ordering::Acquire) & !WAITING {
+      READY => Ok(self.take()),
+      EMPTY | WRITING => Err(TryRecvError::Empty),
+      _ => Err(TryRecvError::Disconnected),
+    }
+  }
+
+  pub fn recv(mut self) -> Result<T, RecvError> {
+    self.recv_deadline(None).map_err(|_| RecvError)
+  }
+
+  pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+    self.recv_deadline(Instant::now().checked_add(timeout))
+  }
+
+  fn recv_deadline(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+    let state = &self.chan.state;
+    let policy = SpinPolicy::exponential();
+    let mut backoff = Backoff::new(&policy);
+    loop {
+      let s = state.load(Ordering::Acquire);
+      match s & !WAITING {
+        READY => return Ok(self.take()),
+        // Only for a moment, unless the sender was preempted while
+        // writing, hence yielding eventually, and still timing out.
+        WRITING => {
+          if deadline.is_some_and(|d| Instant::now() >= d) {
+            // As below, taking our thread back if we left it.
+            if state
+              .compare_exchange(s, WRITING, Ordering::Relaxed, Ordering::Relaxed)
+              .is_ok()
+            {
+              return Err(RecvTimeoutError::Timeout);
+            }
+            continue;
+          }
+          backoff.snooze();
+        }
+        EMPTY => {
+          if s & WAITING == 0 {
+            unsafe { *self.chan.waiter.get() = Some(thread::current()) };
+            // Release to publish `waiter`.
+            if state
+              .compare_exchange(EMPTY, EMPTY | WAITING, Ordering::Release, Ordering::Relaxed)
+              .is_err()
+            {
+              continue;
+            }
+          }
+          match deadline {
+            None => thread::park(),
+            Some(deadline) => {
+              let now = Instant::now();
+              if now >= deadline {
+                // Take our thread back, unless a message or disconnection
+                // came in between.
+                if state
+                  .compare_exchange(EMPTY | WAITING, EMPTY, Ordering::Relaxed, Ordering::Relaxed)
+                  .is_ok()
+                {
+                  return Err(RecvTimeoutError::Timeout);
+                }
+                continue;
+              }
+              thread::park_timeout(deadline - now);
+            }
+          }
+        }
+        _ => return Err(RecvTimeoutError::Disconnected),
+      }
+    }
+  }
+
+  // READY -> TAKEN.
+  fn take(&mut self) -> T {
+    self.chan.state.store(TAKEN, Ordering::Relaxed);
+    unsafe { (*self.chan.msg.get()).assume_init_read() }
+  }
+
+  // Makes `send` fail from now on. A message that's already on its way
+  // can still be received.
+  pub fn close(&mut self) {
+    let _ = self
+      .chan
+      .state
+      .compare_exchange(EMPTY, CLOSED, Ordering::Relaxed, Ordering::Relaxed);
+  }
+}
+
+impl<T> Drop for Receiver<T> {
+  fn drop(&mut self) {
+    // Otherwise, the sender takes its message back when it's done writing.
+    if self.chan.state.swap(CLOSED, Ordering::Acquire) == READY {
+      unsafe { (*self.chan.msg.get()).assume_init_drop() };
     }
-    unsafe { (*self.msg.get()).assume_init_read() }
   }
 }
 
 impl<T> Drop for Channel<T> {
   fn drop(&mut self) {
-    unsafe {
-      if *self.state.load(Ordering::Acquire) != State::Empty {
-        return;
-      }
-      (*self.msg.get()).assume_init_drop();
+    // The receiver drops an unreceived message, unless it was leaked.
+    if *self.state.get_mut() == READY {
+      unsafe { self.msg.get_mut().assume_init_drop() };
     }
   }
 }
 
 #[cfg(test)]
 mod tests {
-  use super::Channel;
-  use std::thread;
+  use super::*;
 
   #[test]
   fn test_channel() {
-    let chan = Channel::new();
-    let t = thread::current();
+    let (sender, receiver) = channel();
     thread::scope(|s| {
       s.spawn(|| {
-        chan.send("hello");
-        // This would panic since we can only send one message
-        // chan.send("world");
-        t.unpark();
+        sender.send("hello").unwrap();
       });
-      while !chan.is_ready() {
-        thread::park();
-      }
-      dbg!(chan.receive());
+      assert_eq!(receiver.recv(), Ok("hello"));
     })
   }
+
+  #[test]
+  fn test_try_recv() {
+    let (sender, mut receiver) = channel();
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    assert!(!sender.is_closed());
+    sender.send(1).unwrap();
+    assert_eq!(receiver.chan.state.load(Ordering::Relaxed), READY);
+    assert_eq!(receiver.try_recv(), Ok(1));
+    assert_eq!(receiver.chan.state.load(Ordering::Relaxed), TAKEN);
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    assert_eq!(
+      receiver.recv_timeout(Duration::ZERO),
+      Err(RecvTimeoutError::Disconnected)
+    );
+  }
+
+  #[test]
+  fn test_close() {
+    let (sender, mut receiver) = channel();
+    receiver.close();
+    assert!(sender.is_closed());
+    assert_eq!(sender.send(1), Err(1));
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+
+    // Too late to close once it's sent.
+    let (sender, mut receiver) = channel();
+    sender.send(2).unwrap();
+    receiver.close();
+    assert_eq!(receiver.recv(), Ok(2));
+  }
+
+  #[test]
+  fn test_receiver_dropped() {
+    let (sender, receiver) = channel();
+    drop(receiver);
+    assert!(sender.is_closed());
+    assert_eq!(sender.send(1), Err(1));
+
+    // An unreceived message is dropped along with the receiver.
+    let msg = Arc::new(());
+    let (sender, receiver) = channel();
+    sender.send(msg.clone()).unwrap();
+    assert_eq!(Arc::strong_count(&msg), 2);
+    drop(receiver);
+    assert_eq!(Arc::strong_count(&msg), 1);
+  }
+
+  #[test]
+  fn test_receiver_dropped_while_writing() {
+    let msg = Arc::new(());
+    let (sender, receiver) = channel();
+    // `send`, interrupted half-way by the receiver going away.
+    assert!(sender.start_writing());
+    unsafe { (*sender.chan.msg.get()).write(msg.clone()) };
+    assert_eq!(sender.chan.state.load(Ordering::Relaxed), WRITING);
+    drop(receiver);
+    let back = sender.finish_writing().unwrap_err();
+    assert!(Arc::ptr_eq(&back, &msg));
+    drop((back, sender));
+    assert_eq!(Arc::strong_count(&msg), 1);
+  }
+
+  #[test]
+  fn test_recv_while_writing() {
+    let (sender, receiver) = channel();
+    assert!(sender.start_writing());
+    unsafe { (*sender.chan.msg.get()).write(1) };
+    thread::scope(|s| {
+      s.spawn(move || {
+        // A slow write, which `recv` waits out without parking.
+        thread::sleep(Duration::from_millis(50));
+        sender.finish_writing().unwrap();
+      });
+      assert_eq!(receiver.recv(), Ok(1));
+    });
+  }
+
+  #[test]
+  fn test_recv_timeout_while_writing() {
+    let (sender, mut receiver) = channel();
+    assert!(sender.start_writing());
+    unsafe { (*sender.chan.msg.get()).write(1) };
+    assert_eq!(
+      receiver.recv_timeout(Duration::ZERO),
+      Err(RecvTimeoutError::Timeout)
+    );
+    let start = Instant::now();
+    assert_eq!(
+      receiver.recv_timeout(Duration::from_millis(20)),
+      Err(RecvTimeoutError::Timeout)
+    );
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    sender.finish_writing().unwrap();
+    assert_eq!(receiver.try_recv(), Ok(1));
+  }
+
+  #[test]
+  fn test_sender_dropped() {
+    let (sender, mut receiver) = channel::<i32>();
+    drop(sender);
+    assert_eq!(receiver.chan.state.load(Ordering::Relaxed), DISCONNECTED);
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    assert_eq!(receiver.recv(), Err(RecvError));
+
+    // Wakes up a blocked receiver.
+    let (sender, receiver) = channel::<i32>();
+    thread::scope(|s| {
+      s.spawn(|| {
+        thread::sleep(Duration::from_millis(50));
+        drop(sender);
+      });
+      assert_eq!(receiver.recv(), Err(RecvError));
+    });
+  }
+
+  #[test]
+  fn test_recv_timeout() {
+    let (sender, mut receiver) = channel();
+    assert_eq!(
+      receiver.recv_timeout(Duration::from_millis(20)),
+      Err(RecvTimeoutError::Timeout)
+    );
+    // Timing out took the receiver's thread back out.
+    assert_eq!(receiver.chan.state.load(Ordering::Relaxed), EMPTY);
+    thread::scope(|s| {
+      s.spawn(|| {
+        thread::sleep(Duration::from_millis(50));
+        sender.send(1).unwrap();
+      });
+      assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(1));
+    });
+  }
 }
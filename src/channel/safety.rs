@@ -1,13 +1,24 @@
 use std::{
   cell::UnsafeCell,
+  error::Error,
+  fmt,
   mem::MaybeUninit,
-  sync::{Arc, atomic::AtomicBool, atomic::Ordering},
+  sync::{
+    Arc, OnceLock,
+    atomic::{AtomicBool, Ordering, fence},
+  },
+  thread::{self, Thread},
 };
 
 struct Channel<T> {
   // no longer `pub`
   message: UnsafeCell<MaybeUninit<T>>,
   ready: AtomicBool,
+  // Cleared when the `Sender` is dropped, sent or not.
+  sender_alive: AtomicBool,
+  receiver_alive: AtomicBool,
+  // Set by a receiver blocked in `recv`, for the sender to unpark.
+  receiver: OnceLock<Thread>,
 }
 
 unsafe impl<T> Sync for Channel<T> where T: Send {}
@@ -20,10 +31,25 @@ pub struct Receiver<T> {
   chan: Arc<Channel<T>>,
 }
 
+// The sender was dropped without sending anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    "receiving on a closed channel".fmt(f)
+  }
+}
+
+impl Error for RecvError {}
+
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
   let chan = Arc::new(Channel {
     message: UnsafeCell::new(MaybeUninit::uninit()),
     ready: AtomicBool::new(false),
+    sender_alive: AtomicBool::new(true),
+    receiver_alive: AtomicBool::new(true),
+    receiver: OnceLock::new(),
   });
   (
     Sender { chan: chan.clone() },
@@ -32,30 +58,73 @@ pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
 }
 
 impl<T> Sender<T> {
-  pub fn send(self, msg: T) {
+  // Gives the message back if the receiver is gone.
+  pub fn send(self, msg: T) -> Result<(), T> {
+    if !self.chan.receiver_alive.load(Ordering::Relaxed) {
+      return Err(msg);
+    }
     unsafe {
       (*self.chan.message.get()).write(msg);
     }
     self.chan.ready.store(true, Ordering::Release);
+    // Dropping `self` wakes the receiver.
+    Ok(())
+  }
+}
+
+impl<T> Drop for Sender<T> {
+  fn drop(&mut self) {
+    self.chan.sender_alive.store(false, Ordering::Release);
+    // Pairs with the fence in `recv`: either we see the receiver's thread,
+    // or it sees that we're gone.
+    fence(Ordering::SeqCst);
+    if let Some(t) = self.chan.receiver.get() {
+      t.unpark();
+    }
   }
 }
 
 impl<T> Receiver<T> {
+  // Whether `receive` would return right away: there is a message, or
+  // there never will be.
   pub fn is_ready(&self) -> bool {
-    self.chan.ready.load(Ordering::Relaxed)
+    self.chan.ready.load(Ordering::Relaxed) || !self.chan.sender_alive.load(Ordering::Relaxed)
   }
 
-  pub fn receive(self) -> T {
-    if !self.chan.ready.swap(false, Ordering::Acquire) {
-      panic!("no message available!");
+  // Panics if the sender is still around, but hasn't sent yet.
+  pub fn receive(self) -> Result<T, RecvError> {
+    // Looked at first, as a sender may send right before going away.
+    let sender_gone = !self.chan.sender_alive.load(Ordering::Acquire);
+    if self.chan.ready.swap(false, Ordering::Acquire) {
+      return Ok(unsafe { (*self.chan.message.get()).assume_init_read() });
     }
-    unsafe { (*self.chan.message.get()).assume_init_read() }
+    if sender_gone {
+      return Err(RecvError);
+    }
+    panic!("no message available!");
+  }
+
+  // Blocks until there is a message, or the sender is gone.
+  pub fn recv(self) -> Result<T, RecvError> {
+    // Only ever one receiver, which can't be waiting elsewhere already.
+    let _ = self.chan.receiver.set(thread::current());
+    fence(Ordering::SeqCst);
+    while !self.is_ready() {
+      thread::park();
+    }
+    self.receive()
+  }
+}
+
+impl<T> Drop for Receiver<T> {
+  fn drop(&mut self) {
+    self.chan.receiver_alive.store(false, Ordering::Relaxed);
   }
 }
 
 impl<T> Drop for Channel<T> {
   fn drop(&mut self) {
-    if (*self.ready.get_mut()) {
+    if *self.ready.get_mut() {
       unsafe {
         (*self.message.get_mut()).assume_init_drop();
       }
@@ -65,21 +134,74 @@ impl<T> Drop for Channel<T> {
 
 #[cfg(test)]
 mod tests {
-  use super::channel;
-  use std::thread;
+  use super::*;
+  use std::time::Duration;
+
   #[test]
   fn test_channel() {
     thread::scope(|s| {
       let (sender, receiver) = channel();
       let t = thread::current();
       s.spawn(move || {
-        sender.send("hello world!");
+        sender.send("hello world!").unwrap();
         t.unpark();
       });
       while !receiver.is_ready() {
         thread::park();
       }
-      dbg!(receiver.receive());
+      dbg!(receiver.receive().unwrap());
     });
   }
+
+  #[test]
+  fn test_recv() {
+    thread::scope(|s| {
+      let (sender, receiver) = channel();
+      s.spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        sender.send(1).unwrap();
+      });
+      assert_eq!(receiver.recv(), Ok(1));
+    });
+  }
+
+  #[test]
+  fn test_sender_dropped() {
+    let (sender, receiver) = channel::<i32>();
+    drop(sender);
+    assert!(receiver.is_ready());
+    assert_eq!(receiver.receive(), Err(RecvError));
+
+    thread::scope(|s| {
+      let (sender, receiver) = channel::<i32>();
+      s.spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        drop(sender);
+      });
+      assert_eq!(receiver.recv(), Err(RecvError));
+    });
+
+    // A message sent before going away still arrives.
+    let (sender, receiver) = channel();
+    sender.send(2).unwrap();
+    assert_eq!(receiver.receive(), Ok(2));
+  }
+
+  #[test]
+  fn test_receiver_dropped() {
+    let msg = Arc::new(());
+    let (sender, receiver) = channel();
+    drop(receiver);
+    let back = sender.send(msg.clone()).unwrap_err();
+    assert!(Arc::ptr_eq(&back, &msg));
+    drop(back);
+    assert_eq!(Arc::strong_count(&msg), 1);
+  }
+
+  #[test]
+  #[should_panic(expected = "no message available!")]
+  fn test_receive_too_early() {
+    let (_sender, receiver) = channel::<i32>();
+    let _ = receiver.receive();
+  }
 }
@@ -0,0 +1,378 @@
+use std::{
+  cell::UnsafeCell,
+  error::Error,
+  fmt,
+  mem::MaybeUninit,
+  ptr,
+  sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering, fence},
+  },
+};
+
+use atomic_wait::{wait, wake_one};
+
+use super::bounded::{Padded, SendError};
+use crate::lock::backoff::{Backoff, SpinPolicy};
+
+struct Node<T> {
+  next: AtomicPtr<Node<T>>,
+  // Uninitialized in the stub at the front of the queue.
+  value: MaybeUninit<T>,
+}
+
+fn new_node<T>(value: MaybeUninit<T>) -> *mut Node<T> {
+  Box::into_raw(Box::new(Node {
+    next: AtomicPtr::new(ptr::null_mut()),
+    value,
+  }))
+}
+
+// An unbounded multi-producer single-consumer queue, after Dmitry Vyukov's:
+// a linked list where a sender swaps its node in as the new back and only
+// then links the old back to it, so sending is a single swap. The front is
+// a stub whose successor holds the next message; receiving moves the stub
+// along and frees the old one. In between a sender's swap and link the
+// list is broken: `try_recv` reports the channel empty, and `recv` waits
+// for the link.
+struct Channel<T> {
+  // Where senders append.
+  back: Padded<AtomicPtr<Node<T>>>,
+  // The stub. Only touched by the receiver.
+  front: UnsafeCell<*mut Node<T>>,
+  // 1 while the receiver is, or is about to be, asleep on it.
+  parked: AtomicU32,
+  senders: AtomicUsize,
+  receiver_alive: AtomicBool,
+}
+
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+unsafe impl<T> Send for Channel<T> where T: Send {}
+
+pub struct Sender<T> {
+  chan: Arc<Channel<T>>,
+}
+
+// Only one, so receiving takes `&mut self`.
+pub struct Receiver<T> {
+  chan: Arc<Channel<T>>,
+}
+
+// All senders are gone, and so are their messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    "receiving on a closed channel".fmt(f)
+  }
+}
+
+impl Error for RecvError {}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+  let stub = new_node(MaybeUninit::uninit());
+  let chan = Arc::new(Channel {
+    back: Padded(AtomicPtr::new(stub)),
+    front: UnsafeCell::new(stub),
+    parked: AtomicU32::new(0),
+    senders: AtomicUsize::new(1),
+    receiver_alive: AtomicBool::new(true),
+  });
+  (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+impl<T> Channel<T> {
+  // Wakes the receiver if it sleeps, after a send or the last sender left.
+  fn notify(&self) {
+    // Pairs with the fence in `recv`: either we see it parked, or it sees
+    // what we did before going to sleep.
+    fence(Ordering::SeqCst);
+    if self.parked.load(Ordering::Relaxed) == 1 && self.parked.swap(0, Ordering::Relaxed) == 1 {
+      wake_one(&self.parked);
+    }
+  }
+
+  // Only called by the receiver. None if the channel is empty, or if the
+  // next message's sender didn't link it yet.
+  unsafe fn pop(&self) -> Option<T> {
+    let front = unsafe { *self.front.get() };
+    let next = unsafe { (*front).next.load(Ordering::Acquire) };
+    if next.is_null() {
+      return None;
+    }
+    // `next` becomes the stub.
+    let value = unsafe { (*next).value.assume_init_read() };
+    unsafe { *self.front.get() = next };
+    drop(unsafe { Box::from_raw(front) });
+    Some(value)
+  }
+
+  // Only called by the receiver, after `pop` found nothing: whether a
+  // sender swapped itself in, but didn't link yet.
+  unsafe fn is_linking(&self) -> bool {
+    self.back.load(Ordering::Acquire) != unsafe { *self.front.get() }
+  }
+}
+
+impl<T> Sender<T> {
+  // Gives the message back if the receiver is gone.
+  pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+    if !self.chan.receiver_alive.load(Ordering::Relaxed) {
+      return Err(SendError(msg));
+    }
+    let node = new_node(MaybeUninit::new(msg));
+    let prev = self.chan.back.swap(node, Ordering::AcqRel);
+    unsafe { (*prev).next.store(node, Ordering::Release) };
+    self.chan.notify();
+    Ok(())
+  }
+}
+
+impl<T> Clone for Sender<T> {
+  fn clone(&self) -> Self {
+    self.chan.senders.fetch_add(1, Ordering::Relaxed);
+    Sender {
+      chan: self.chan.clone(),
+    }
+  }
+}
+
+impl<T> Drop for Sender<T> {
+  fn drop(&mut self) {
+    // Release: the receiver drains our messages after seeing 0.
+    if self.chan.senders.fetch_sub(1, Ordering::Release) == 1 {
+      self.chan.notify();
+    }
+  }
+}
+
+impl<T> Receiver<T> {
+  // Err once the channel is empty and all senders are gone. Ok(None) also
+  // while the next message's sender is in between its swap and link.
+  pub fn try_recv(&mut self) -> Result<Option<T>, RecvError> {
+    if let Some(msg) = unsafe { self.chan.pop() } {
+      return Ok(Some(msg));
+    }
+    if self.chan.senders.load(Ordering::Acquire) != 0 {
+      return Ok(None);
+    }
+    // One last look, for what was sent right before leaving. All senders
+    // linked their messages by now.
+    match unsafe { self.chan.pop() } {
+      Some(msg) => Ok(Some(msg)),
+      None => Err(RecvError),
+    }
+  }
+
+  // Only blocks while the channel is empty.
+  pub fn recv(&mut self) -> Result<T, RecvError> {
+    let policy = SpinPolicy::exponential();
+    let mut backoff = Backoff::new(&policy);
+    loop {
+      if let Some(msg) = self.try_recv()? {
+        return Ok(msg);
+      }
+      // Only a matter of a few instructions, unless the sender was
+      // preempted in between, hence yielding eventually.
+      if unsafe { self.chan.is_linking() } {
+        backoff.snooze();
+        continue;
+      }
+      self.chan.parked.store(1, Ordering::Relaxed);
+      fence(Ordering::SeqCst);
+      match self.try_recv() {
+        Ok(None) => wait(&self.chan.parked, 1),
+        r => {
+          self.chan.parked.store(0, Ordering::Relaxed);
+          if let Some(msg) = r? {
+            return Ok(msg);
+          }
+        }
+      }
+    }
+  }
+
+  // Doesn't block.
+  pub fn try_iter(&mut self) -> impl Iterator<Item = T> + '_ {
+    std::iter::from_fn(|| self.try_recv().ok().flatten())
+  }
+}
+
+impl<T> Drop for Receiver<T> {
+  fn drop(&mut self) {
+    self.chan.receiver_alive.store(false, Ordering::Relaxed);
+  }
+}
+
+impl<T> Drop for Channel<T> {
+  fn drop(&mut self) {
+    // No sender is left, let alone one halfway through linking.
+    while unsafe { self.pop() }.is_some() {}
+    drop(unsafe { Box::from_raw(*self.front.get_mut()) });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::channel::mutex_chan;
+  use std::{
+    thread,
+    time::{Duration, Instant},
+  };
+
+  #[test]
+  fn test_channel() {
+    let (sender, mut receiver) = channel();
+    assert_eq!(receiver.try_recv(), Ok(None));
+    for i in 0..10 {
+      sender.send(i).unwrap();
+    }
+    assert_eq!(receiver.recv(), Ok(0));
+    assert_eq!(
+      receiver.try_iter().collect::<Vec<_>>(),
+      (1..10).collect::<Vec<_>>()
+    );
+    sender.send(10).unwrap();
+    drop(sender);
+    // Still delivered after the sender left.
+    assert_eq!(receiver.recv(), Ok(10));
+    assert_eq!(receiver.recv(), Err(RecvError));
+    assert_eq!(receiver.try_recv(), Err(RecvError));
+  }
+
+  #[test]
+  fn test_blocking() {
+    let (sender, mut receiver) = channel();
+    thread::scope(|s| {
+      s.spawn(move || {
+        for i in 0..3 {
+          thread::sleep(Duration::from_millis(20));
+          sender.send(i).unwrap();
+        }
+      });
+      for i in 0..3 {
+        assert_eq!(receiver.recv(), Ok(i));
+      }
+      // Woken by the last sender leaving.
+      assert_eq!(receiver.recv(), Err(RecvError));
+    });
+  }
+
+  #[test]
+  fn test_mpsc() {
+    let (sender, mut receiver) = channel();
+    thread::scope(|s| {
+      for p in 0..4 {
+        let sender = sender.clone();
+        s.spawn(move || {
+          for i in 0..10_000 {
+            sender.send(p * 10_000 + i).unwrap();
+          }
+        });
+      }
+      drop(sender);
+      let mut last = [None; 4];
+      let mut total = 0u64;
+      while let Ok(msg) = receiver.recv() {
+        // In order per sender.
+        let p = (msg / 10_000) as usize;
+        assert!(last[p] < Some(msg));
+        last[p] = Some(msg);
+        total += msg;
+      }
+      assert_eq!(total, (0..40_000).sum());
+    });
+  }
+
+  #[test]
+  fn test_drop() {
+    let msg = Arc::new(());
+    let (sender, mut receiver) = channel();
+    for _ in 0..3 {
+      sender.send(msg.clone()).unwrap();
+    }
+    receiver.recv().unwrap();
+    assert_eq!(Arc::strong_count(&msg), 3);
+    drop(receiver);
+    let SendError(back) = sender.send(msg.clone()).unwrap_err();
+    assert!(Arc::ptr_eq(&back, &msg));
+    drop(back);
+    drop(sender);
+    assert_eq!(Arc::strong_count(&msg), 1);
+  }
+
+  #[test]
+  fn test_unlinked() {
+    let (sender, mut receiver) = channel();
+    // `send`, stopped in between the swap and the link.
+    let node = new_node(MaybeUninit::new(1));
+    let prev = sender.chan.back.swap(node, Ordering::AcqRel);
+    assert_eq!(receiver.try_recv(), Ok(None));
+    unsafe { (*prev).next.store(node, Ordering::Release) };
+    assert_eq!(receiver.try_recv(), Ok(Some(1)));
+
+    // `recv` waits for the link, without being woken.
+    let node = new_node(MaybeUninit::new(2));
+    let prev = sender.chan.back.swap(node, Ordering::AcqRel);
+    // Raw pointers aren't Send.
+    let (node, prev) = (node as usize, prev as usize);
+    thread::scope(|s| {
+      s.spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        let prev = prev as *mut Node<i32>;
+        unsafe { (*prev).next.store(node as *mut _, Ordering::Release) };
+      });
+      assert_eq!(receiver.recv(), Ok(2));
+    });
+  }
+
+  const MESSAGES: usize = 200_000;
+
+  fn bench(producers: usize) {
+    let per_producer = MESSAGES / producers;
+
+    let (sender, mut receiver) = channel();
+    let start = Instant::now();
+    thread::scope(|s| {
+      for _ in 0..producers {
+        let sender = sender.clone();
+        s.spawn(move || {
+          for i in 0..per_producer {
+            sender.send(i).unwrap();
+          }
+        });
+      }
+      for _ in 0..per_producer * producers {
+        std::hint::black_box(receiver.recv().unwrap());
+      }
+    });
+    let unbounded = start.elapsed();
+
+    let chan = mutex_chan::Channel::new();
+    let start = Instant::now();
+    thread::scope(|s| {
+      for _ in 0..producers {
+        s.spawn(|| {
+          for i in 0..per_producer {
+            chan.send(i);
+          }
+        });
+      }
+      for _ in 0..per_producer * producers {
+        std::hint::black_box(chan.receive());
+      }
+    });
+    let mutex_chan = start.elapsed();
+
+    dbg!(producers, unbounded, mutex_chan);
+  }
+
+  #[test]
+  fn unbounded_channel_benchmark() {
+    for producers in [1, 2, 4, 8, 16] {
+      bench(producers);
+    }
+  }
+}
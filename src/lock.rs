@@ -1,13 +1,31 @@
+pub(crate) mod backoff;
+mod clh;
 pub(crate) mod condvar;
+pub(crate) mod futex;
+mod lockdep;
+mod mcs;
 pub(crate) mod mutex;
+mod poison;
+pub(crate) mod raw;
+mod reentrant;
 mod rwlock;
+mod seqlock;
+mod sharded;
 mod spin;
+mod ticket;
 mod unsafe_spin;
 
 #[cfg(test)]
 mod tests {
+  use super::backoff::SpinPolicy;
+  use super::clh::ClhLock;
+  use super::mcs::{McsLock, McsNode};
+  use super::ticket::TicketLock;
   use super::unsafe_spin::UnsafeSpinLock;
+  use std::pin::pin;
+  use std::sync::atomic::{AtomicBool, Ordering};
   use std::thread;
+  use std::time::{Duration, Instant};
 
   #[test]
   fn test_spinlock() {
@@ -24,4 +42,74 @@ mod tests {
     let g = x.lock();
     assert!(g.as_slice() == [1, 2, 2] || g.as_slice() == [2, 2, 1]);
   }
+
+  #[test]
+  fn spin_policy_benchmark() {
+    let policies = [
+      ("forever", SpinPolicy::FOREVER),
+      ("fixed", SpinPolicy::DEFAULT),
+      ("exponential", SpinPolicy::exponential()),
+      ("adaptive", SpinPolicy::adaptive()),
+    ];
+    for (name, policy) in policies {
+      let x = UnsafeSpinLock::new(0).spin_policy(policy);
+      let start = Instant::now();
+      thread::scope(|s| {
+        for _ in 0..4 {
+          s.spawn(|| {
+            for _ in 0..1_000_000 {
+              *x.lock() += 1;
+            }
+          });
+        }
+      });
+      assert_eq!(*x.lock(), 4_000_000);
+      dbg!(name, start.elapsed());
+    }
+  }
+
+  // Runs `critical` (lock, increment, unlock) on 4 threads for a while.
+  // Throughput is the total count, fairness how even the per-thread
+  // counts are.
+  fn fifo_bench(name: &str, critical: impl Fn() + Sync) -> u64 {
+    let stop = AtomicBool::new(false);
+    let counts: Vec<u64> = thread::scope(|s| {
+      let threads: Vec<_> = (0..4)
+        .map(|_| {
+          s.spawn(|| {
+            let mut n = 0u64;
+            while !stop.load(Ordering::Relaxed) {
+              critical();
+              n += 1;
+            }
+            n
+          })
+        })
+        .collect();
+      thread::sleep(Duration::from_millis(200));
+      stop.store(true, Ordering::Relaxed);
+      threads.into_iter().map(|t| t.join().unwrap()).collect()
+    });
+    let total: u64 = counts.iter().sum();
+    let min = *counts.iter().min().unwrap();
+    let max = *counts.iter().max().unwrap();
+    dbg!(name, total, min, max);
+    total
+  }
+
+  #[test]
+  fn fifo_lock_benchmark() {
+    let unfair = UnsafeSpinLock::new(0u64).spin_policy(SpinPolicy::exponential());
+    let total = fifo_bench("unsafe_spin", || *unfair.lock() += 1);
+    assert_eq!(*unfair.lock(), total);
+    let ticket = TicketLock::new(0u64);
+    let total = fifo_bench("ticket", || *ticket.lock() += 1);
+    assert_eq!(*ticket.lock(), total);
+    let mcs = McsLock::new(0u64);
+    let total = fifo_bench("mcs", || *mcs.lock(pin!(McsNode::new())) += 1);
+    assert_eq!(*mcs.lock(pin!(McsNode::new())), total);
+    let clh = ClhLock::new(0u64);
+    let total = fifo_bench("clh", || *clh.lock() += 1);
+    assert_eq!(*clh.lock(), total);
+  }
 }
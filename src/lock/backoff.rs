@@ -0,0 +1,117 @@
+use std::{
+  hint,
+  sync::atomic::{AtomicU32, Ordering},
+  thread,
+};
+
+// How long to busy-wait for a lock before giving up on spinning,
+// i.e. before parking (futex locks) or yielding (pure spin locks).
+pub enum SpinPolicy {
+  // Spin this many rounds of `spin_loop`.
+  Fixed(u32),
+  // Spin 1, 2, 4, .. rounds per step up to step `spin_limit`, then yield
+  // the thread once per step up to step `yield_limit`, like crossbeam.
+  Exponential { spin_limit: u32, yield_limit: u32 },
+  // Like `Fixed`, but the number of rounds follows how long spinning
+  // recently took to succeed (much like glibc's adaptive mutexes).
+  // Holds that running average.
+  Adaptive(AtomicU32),
+}
+
+const ADAPTIVE_MAX: u32 = 1000;
+
+impl SpinPolicy {
+  // What `lock::mutex::Mutex` always used to do.
+  pub const DEFAULT: Self = Self::Fixed(100);
+  pub const NONE: Self = Self::Fixed(0);
+  pub const FOREVER: Self = Self::Fixed(u32::MAX);
+
+  pub const fn exponential() -> Self {
+    Self::Exponential {
+      spin_limit: 6,
+      yield_limit: 10,
+    }
+  }
+
+  pub const fn adaptive() -> Self {
+    Self::Adaptive(AtomicU32::new(0))
+  }
+}
+
+// The state of a single lock attempt under a `SpinPolicy`.
+pub struct Backoff<'a> {
+  policy: &'a SpinPolicy,
+  limit: u32,
+  rounds: u32,
+  step: u32,
+}
+
+impl<'a> Backoff<'a> {
+  pub fn new(policy: &'a SpinPolicy) -> Self {
+    let limit = match policy {
+      SpinPolicy::Fixed(n) => *n,
+      SpinPolicy::Exponential { .. } => 0,
+      SpinPolicy::Adaptive(avg) => (avg.load(Ordering::Relaxed) * 2 + 10).min(ADAPTIVE_MAX),
+    };
+    Self {
+      policy,
+      limit,
+      rounds: 0,
+      step: 0,
+    }
+  }
+
+  // Backs off once. Returns false, without waiting, once the policy says
+  // spinning is no longer worth it.
+  pub fn spin(&mut self) -> bool {
+    match *self.policy {
+      SpinPolicy::Exponential {
+        spin_limit,
+        yield_limit,
+      } => {
+        if self.step <= spin_limit {
+          for _ in 0..1u32 << self.step {
+            hint::spin_loop();
+          }
+          self.rounds += 1 << self.step;
+        } else if self.step <= yield_limit {
+          thread::yield_now();
+        } else {
+          return false;
+        }
+        self.step += 1;
+      }
+      _ => {
+        if self.rounds >= self.limit {
+          return false;
+        }
+        hint::spin_loop();
+        self.rounds += 1;
+      }
+    }
+    true
+  }
+
+  // For locks that never park: backs off, yielding the thread once
+  // spinning is no longer worth it.
+  pub fn snooze(&mut self) {
+    if !self.spin() {
+      thread::yield_now();
+    }
+  }
+
+  // Tells the policy whether spinning got us the lock.
+  pub fn finish(self, acquired: bool) {
+    if let SpinPolicy::Adaptive(avg) = self.policy {
+      let old = avg.load(Ordering::Relaxed);
+      let new = if acquired {
+        // Move an eighth of the way towards what it took this time.
+        (old * 7 + self.rounds.min(ADAPTIVE_MAX)) / 8
+      } else {
+        old - old / 8
+      };
+      // Racy, but it's only a hint.
+      avg.store(new, Ordering::Relaxed);
+    }
+  }
+}
@@ -0,0 +1,165 @@
+use std::{
+  cell::UnsafeCell,
+  ops::{Deref, DerefMut},
+  ptr,
+  sync::atomic::{
+    AtomicBool, AtomicPtr,
+    Ordering::{AcqRel, Acquire, Relaxed, Release},
+  },
+};
+
+use super::backoff::{Backoff, SpinPolicy};
+use super::lockdep;
+
+struct Node {
+  // Whether the owner holds or waits for the lock.
+  locked: AtomicBool,
+}
+
+// A FIFO spin lock where every waiter spins on its predecessor's node.
+// Unlike MCS, the queue is only linked implicitly through `tail`, and a
+// thread takes over its predecessor's node once it has the lock. Here
+// nodes are boxed, and the one taken over is left in `spare` on unlock
+// for the next `lock` to use, so the lock doesn't allocate once warm.
+pub struct ClhLock<T> {
+  // Null until first locked, then the last node in line.
+  tail: AtomicPtr<Node>,
+  // A node nobody looks at anymore, or null.
+  spare: AtomicPtr<Node>,
+  spin: SpinPolicy,
+  class: lockdep::Class,
+  value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for ClhLock<T> where T: Send {}
+
+pub struct Guard<'a, T> {
+  lock: &'a ClhLock<T>,
+  node: *const Node,
+  // The predecessor's node, now ours. Null for the first lock.
+  prev: *mut Node,
+}
+
+impl<T> ClhLock<T> {
+  pub const fn new(value: T) -> Self {
+    ClhLock {
+      tail: AtomicPtr::new(ptr::null_mut()),
+      spare: AtomicPtr::new(ptr::null_mut()),
+      // As for `TicketLock`: yield eventually.
+      spin: SpinPolicy::exponential(),
+      class: lockdep::Class::new(),
+      value: UnsafeCell::new(value),
+    }
+  }
+
+  pub const fn spin_policy(mut self, spin: SpinPolicy) -> Self {
+    self.spin = spin;
+    self
+  }
+
+  pub fn lock(&self) -> Guard<'_, T> {
+    lockdep::acquire(&self.class);
+    let node = self.spare.swap(ptr::null_mut(), Acquire);
+    let node = if node.is_null() {
+      Box::into_raw(Box::new(Node {
+        locked: AtomicBool::new(true),
+      }))
+    } else {
+      unsafe { (*node).locked.store(true, Relaxed) };
+      node
+    };
+    // Release: a successor finds our initialized node through `tail`.
+    let prev = self.tail.swap(node, AcqRel);
+    if !prev.is_null() {
+      let mut backoff = Backoff::new(&self.spin);
+      while unsafe { (*prev).locked.load(Acquire) } {
+        backoff.snooze();
+      }
+      backoff.finish(true);
+    }
+    lockdep::acquired(&self.class);
+    Guard {
+      lock: self,
+      node,
+      prev,
+    }
+  }
+}
+
+impl<T> Drop for ClhLock<T> {
+  fn drop(&mut self) {
+    for node in [*self.tail.get_mut(), *self.spare.get_mut()] {
+      if !node.is_null() {
+        drop(unsafe { Box::from_raw(node) });
+      }
+    }
+  }
+}
+
+unsafe impl<T: Send> Send for Guard<'_, T> {}
+unsafe impl<T: Sync> Sync for Guard<'_, T> {}
+
+impl<T> Deref for Guard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.lock.value.get() }
+  }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    unsafe { &mut *self.lock.value.get() }
+  }
+}
+
+impl<T> Drop for Guard<'_, T> {
+  fn drop(&mut self) {
+    lockdep::release(&self.lock.class);
+    // Hands the node over to the successor, or leaves it as the tail.
+    unsafe { (*self.node).locked.store(false, Release) };
+    if !self.prev.is_null() {
+      // Nobody else looks at it anymore. Only one is kept.
+      let old = self.lock.spare.swap(self.prev, AcqRel);
+      if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+
+  #[test]
+  fn test_clh_lock() {
+    static L: ClhLock<i32> = ClhLock::new(0);
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..10_000 {
+            *L.lock() += 1;
+          }
+        });
+      }
+    });
+    assert_eq!(*L.lock(), 40_000);
+    let tail = L.tail.load(Relaxed);
+    assert!(!unsafe { (*tail).locked.load(Relaxed) });
+  }
+
+  #[test]
+  fn test_recycled_nodes() {
+    let l = ClhLock::new(0);
+    drop(l.lock());
+    let first = l.tail.load(Relaxed);
+    // The second lock takes over the first one's node ..
+    drop(l.lock());
+    assert_eq!(l.spare.load(Relaxed), first);
+    // .. which the third one locks with.
+    drop(l.lock());
+    assert_eq!(l.tail.load(Relaxed), first);
+  }
+}
@@ -1,12 +1,32 @@
+use crate::lock::futex;
 use crate::lock::mutex::*;
 use atomic_wait::{wait, wake_all, wake_one};
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::thread;
-use std::time::Duration;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+  pub(crate) fn new(timed_out: bool) -> Self {
+    Self(timed_out)
+  }
+
+  pub fn timed_out(&self) -> bool {
+    self.0
+  }
+}
 
 pub struct Condvar {
   counter: AtomicU32,
   num_waiters: AtomicU32,
+  // The futex of the mutex used with this condvar, for `notify_all` to
+  // requeue waiters onto. Dangling once used with more than one mutex.
+  mutex: AtomicPtr<AtomicU32>,
+  // Wake ups in the predicate helpers after which the predicate still
+  // asked to wait. Only a statistic.
+  spurious_wakeups: AtomicUsize,
 }
 
 impl Condvar {
@@ -14,10 +34,36 @@ impl Condvar {
     Self {
       counter: AtomicU32::new(0),
       num_waiters: AtomicU32::new(0),
+      mutex: AtomicPtr::new(ptr::null_mut()),
+      spurious_wakeups: AtomicUsize::new(0),
+    }
+  }
+
+  pub fn spurious_wakeups(&self) -> usize {
+    self.spurious_wakeups.load(Ordering::Relaxed)
+  }
+
+  fn set_mutex(&self, futex: &AtomicU32) {
+    let futex = futex as *const AtomicU32 as *mut AtomicU32;
+    match self
+      .mutex
+      .compare_exchange(ptr::null_mut(), futex, Ordering::SeqCst, Ordering::SeqCst)
+    {
+      Ok(_) => {}
+      Err(prev) if prev == futex => {}
+      Err(_) => {
+        if self.mutex.swap(ptr::dangling_mut(), Ordering::SeqCst) != ptr::dangling_mut() {
+          // A `notify_all` may have loaded the other mutex already. This
+          // makes its requeue fail, as the counter no longer holds what it
+          // expects, and it then finds the dangling pointer.
+          self.counter.fetch_add(1, Ordering::SeqCst);
+        }
+      }
     }
   }
 
   pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+    self.set_mutex(guard.mutex.futex());
     self.num_waiters.fetch_add(1, Ordering::Relaxed);
     let count_val = self.counter.load(Ordering::Relaxed);
 
@@ -30,7 +76,89 @@ impl Condvar {
     wait(&self.counter, count_val);
 
     self.num_waiters.fetch_sub(1, Ordering::Relaxed);
-    mutex.lock()
+    mutex.relock()
+  }
+
+  pub fn wait_timeout<'a, T>(
+    &self,
+    guard: MutexGuard<'a, T>,
+    dur: Duration,
+  ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+    self.set_mutex(guard.mutex.futex());
+    self.num_waiters.fetch_add(1, Ordering::Relaxed);
+    let count_val = self.counter.load(Ordering::Relaxed);
+
+    let mutex = guard.mutex;
+    drop(guard);
+
+    let woken = futex::wait_timeout(&self.counter, count_val, dur);
+
+    // Also on timeout, or notify would keep on waking nobody.
+    self.num_waiters.fetch_sub(1, Ordering::Relaxed);
+    (mutex.relock(), WaitTimeoutResult(!woken))
+  }
+
+  // Waits while `condition` holds, so spurious wake ups are taken care of.
+  pub fn wait_while<'a, T>(
+    &self,
+    mut guard: MutexGuard<'a, T>,
+    mut condition: impl FnMut(&mut T) -> bool,
+  ) -> MutexGuard<'a, T> {
+    if !condition(&mut *guard) {
+      return guard;
+    }
+    loop {
+      guard = self.wait(guard);
+      if !condition(&mut *guard) {
+        return guard;
+      }
+      self.spurious_wakeups.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  pub fn wait_until<'a, T>(
+    &self,
+    guard: MutexGuard<'a, T>,
+    mut condition: impl FnMut(&mut T) -> bool,
+  ) -> MutexGuard<'a, T> {
+    self.wait_while(guard, |v| !condition(v))
+  }
+
+  // Waits while `condition` holds, but for no longer than `dur` in total.
+  // Only reports a timeout if the condition still held at the end.
+  pub fn wait_timeout_while<'a, T>(
+    &self,
+    mut guard: MutexGuard<'a, T>,
+    dur: Duration,
+    mut condition: impl FnMut(&mut T) -> bool,
+  ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+    let start = Instant::now();
+    if !condition(&mut *guard) {
+      return (guard, WaitTimeoutResult(false));
+    }
+    loop {
+      let timeout = match dur.checked_sub(start.elapsed()) {
+        Some(timeout) => timeout,
+        None => return (guard, WaitTimeoutResult(true)),
+      };
+      let (g, result) = self.wait_timeout(guard, timeout);
+      guard = g;
+      if !condition(&mut *guard) {
+        return (guard, WaitTimeoutResult(false));
+      }
+      if !result.timed_out() {
+        self.spurious_wakeups.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+  }
+
+  pub fn wait_timeout_until<'a, T>(
+    &self,
+    guard: MutexGuard<'a, T>,
+    dur: Duration,
+    mut condition: impl FnMut(&mut T) -> bool,
+  ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+    self.wait_timeout_while(guard, dur, |v| !condition(v))
   }
 
   pub fn notify_one(&self) {
@@ -41,6 +169,29 @@ impl Condvar {
   }
 
   pub fn notify_all(&self) {
+    if self.num_waiters.load(Ordering::Relaxed) > 0 {
+      let mut count_val = self.counter.fetch_add(1, Ordering::SeqCst).wrapping_add(1);
+      loop {
+        // Loaded after `count_val`: if a waiter on another mutex got in
+        // since, its `set_mutex` bumped the counter, and the requeue fails.
+        let mutex = self.mutex.load(Ordering::SeqCst);
+        if mutex.is_null() || mutex == ptr::dangling_mut() {
+          wake_all(&self.counter);
+          return;
+        }
+        // Wake just one, and move the others over to the mutex's futex,
+        // where every unlock wakes the next one, rather than waking them
+        // all to stampede on the mutex at once.
+        if futex::requeue_one(&self.counter, count_val, mutex) {
+          return;
+        }
+        count_val = self.counter.load(Ordering::SeqCst);
+      }
+    }
+  }
+
+  // `notify_all` without requeueing, to compare against.
+  pub fn notify_all_without_requeue(&self) {
     if self.num_waiters.load(Ordering::Relaxed) > 0 {
       self.counter.fetch_add(1, Ordering::Relaxed);
 
@@ -83,4 +234,228 @@ mod tests {
     });
     dbg!(*mutex.lock(), wakeups);
   }
+
+  #[test]
+  fn test_wait_timeout() {
+    let mutex = Mutex::new(0);
+    let condvar = Condvar::new();
+
+    let start = Instant::now();
+    let (guard, result) = condvar.wait_timeout(mutex.lock(), Duration::from_millis(50));
+    assert!(result.timed_out());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(condvar.num_waiters.load(Ordering::Relaxed), 0);
+    drop(guard);
+
+    thread::scope(|s| {
+      s.spawn(|| {
+        thread::sleep(Duration::from_millis(50));
+        *mutex.lock() = 1;
+        condvar.notify_one();
+      });
+      let mut guard = mutex.lock();
+      while *guard == 0 {
+        let (g, result) = condvar.wait_timeout(guard, Duration::from_secs(10));
+        assert!(!result.timed_out());
+        guard = g;
+      }
+    });
+    assert_eq!(condvar.num_waiters.load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn test_wait_timeout_while() {
+    let mutex = Mutex::new(0);
+    let condvar = Condvar::new();
+
+    // Periodic housekeeping: notifications that don't satisfy the
+    // condition must not cut the total wait short.
+    thread::scope(|s| {
+      s.spawn(|| {
+        for _ in 0..10 {
+          thread::sleep(Duration::from_millis(10));
+          *mutex.lock() += 1;
+          condvar.notify_all();
+        }
+      });
+      let start = Instant::now();
+      let (guard, result) =
+        condvar.wait_timeout_while(mutex.lock(), Duration::from_millis(300), |n| *n < 100);
+      assert!(result.timed_out());
+      assert!(start.elapsed() >= Duration::from_millis(300));
+      assert_eq!(*guard, 10);
+    });
+
+    let (guard, result) =
+      condvar.wait_timeout_while(mutex.lock(), Duration::from_secs(10), |n| *n < 10);
+    assert!(!result.timed_out());
+    assert_eq!(*guard, 10);
+  }
+
+  #[test]
+  fn test_wait_while() {
+    let mutex = Mutex::new(0);
+    let condvar = Condvar::new();
+    thread::scope(|s| {
+      s.spawn(|| {
+        thread::sleep(Duration::from_millis(100));
+        *mutex.lock() = 123;
+        condvar.notify_one();
+        for _ in 0..1_000_000 {
+          condvar.notify_one();
+        }
+      });
+      let guard = condvar.wait_while(mutex.lock(), |n| *n < 100);
+      assert_eq!(*guard, 123);
+    });
+    assert_eq!(condvar.spurious_wakeups(), 0);
+
+    // Every notification but the last one is a wake up for nothing.
+    let checks = AtomicUsize::new(0);
+    thread::scope(|s| {
+      s.spawn(|| {
+        for _ in 0..10 {
+          thread::sleep(Duration::from_millis(10));
+          *mutex.lock() += 1;
+          condvar.notify_all();
+        }
+      });
+      let guard = condvar.wait_until(mutex.lock(), |n| {
+        checks.fetch_add(1, Ordering::Relaxed);
+        *n >= 133
+      });
+      assert_eq!(*guard, 133);
+    });
+    let checks = checks.load(Ordering::Relaxed);
+    assert!(checks >= 2);
+    assert_eq!(condvar.spurious_wakeups(), checks - 2);
+  }
+
+  #[test]
+  fn test_wait_timeout_until() {
+    let mutex = Mutex::new(0);
+    let condvar = Condvar::new();
+    thread::scope(|s| {
+      s.spawn(|| {
+        for _ in 0..5 {
+          thread::sleep(Duration::from_millis(10));
+          *mutex.lock() += 1;
+          condvar.notify_one();
+        }
+      });
+      let (guard, result) =
+        condvar.wait_timeout_until(mutex.lock(), Duration::from_secs(10), |n| *n == 5);
+      assert!(!result.timed_out());
+      assert_eq!(*guard, 5);
+    });
+    assert!(condvar.spurious_wakeups() <= 4);
+
+    let (_, result) =
+      condvar.wait_timeout_until(mutex.lock(), Duration::from_millis(20), |n| *n == 6);
+    assert!(result.timed_out());
+  }
+
+  #[test]
+  fn test_notify_all_requeue() {
+    let mutex = Mutex::new((false, 0));
+    let condvar = Condvar::new();
+    thread::scope(|s| {
+      for _ in 0..16 {
+        s.spawn(|| {
+          let mut guard = condvar.wait_while(mutex.lock(), |(go, _)| !*go);
+          guard.1 += 1;
+        });
+      }
+      while condvar.num_waiters.load(Ordering::Relaxed) < 16 {
+        thread::sleep(Duration::from_millis(1));
+      }
+      mutex.lock().0 = true;
+      condvar.notify_all();
+    });
+    assert_eq!(mutex.lock().1, 16);
+    assert_eq!(mutex.futex().load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn test_two_mutexes() {
+    // Requeueing onto either mutex would strand the other's waiters.
+    let a = Mutex::new(false);
+    let b = Mutex::new(false);
+    let condvar = Condvar::new();
+    thread::scope(|s| {
+      s.spawn(|| drop(condvar.wait_while(a.lock(), |go| !*go)));
+      s.spawn(|| drop(condvar.wait_while(b.lock(), |go| !*go)));
+      while condvar.num_waiters.load(Ordering::Relaxed) < 2 {
+        thread::sleep(Duration::from_millis(1));
+      }
+      *a.lock() = true;
+      *b.lock() = true;
+      condvar.notify_all();
+    });
+  }
+
+  #[test]
+  fn test_second_mutex_during_notify_all() {
+    // A waiter on `b` arriving while `notify_all` requeues `a`'s waiters
+    // must not be requeued onto `a`, where it would take a wake up meant
+    // for them.
+    for _ in 0..50 {
+      let a = Mutex::new(false);
+      let b = Mutex::new(false);
+      let condvar = Condvar::new();
+      thread::scope(|s| {
+        for _ in 0..4 {
+          s.spawn(|| drop(condvar.wait_while(a.lock(), |go| !*go)));
+        }
+        while condvar.num_waiters.load(Ordering::Relaxed) < 4 {
+          thread::yield_now();
+        }
+        *a.lock() = true;
+        s.spawn(|| drop(condvar.wait_while(b.lock(), |go| !*go)));
+        condvar.notify_all();
+        while condvar.num_waiters.load(Ordering::Relaxed) < 1 {
+          thread::yield_now();
+        }
+        *b.lock() = true;
+        condvar.notify_all();
+      });
+      assert_eq!(a.futex().load(Ordering::Relaxed), 0);
+    }
+  }
+
+  // Time from notify_all until the last of 64 waiters got the mutex.
+  fn notify_all_bench(requeue: bool) {
+    let mutex = Mutex::new((0, Instant::now()));
+    let condvar = Condvar::new();
+    let mut total = Duration::ZERO;
+    for round in 1..=20 {
+      let start = thread::scope(|s| {
+        for _ in 0..64 {
+          s.spawn(|| {
+            let mut guard = condvar.wait_while(mutex.lock(), |(r, _)| *r < round);
+            guard.1 = Instant::now();
+          });
+        }
+        while condvar.num_waiters.load(Ordering::Relaxed) < 64 {
+          thread::sleep(Duration::from_millis(1));
+        }
+        mutex.lock().0 = round;
+        let start = Instant::now();
+        if requeue {
+          condvar.notify_all();
+        } else {
+          condvar.notify_all_without_requeue();
+        }
+        start
+      });
+      total += mutex.lock().1 - start;
+    }
+    dbg!(requeue, total / 20);
+  }
+
+  #[test]
+  fn notify_all_benchmark() {
+    notify_all_bench(false);
+    notify_all_bench(true);
+  }
 }
@@ -0,0 +1,78 @@
+use std::{
+  sync::atomic::AtomicU32,
+  time::{Duration, Instant},
+};
+
+// `atomic_wait` only offers an unbounded wait, so the timed variants
+// talk to the Linux futex syscall directly, with the same private flag.
+
+/// Waits while `a` still holds `expected`, but no later than `deadline`.
+/// Returns `false` if the deadline passed, `true` on a wake up
+/// (which may be spurious, or because the value had already changed).
+pub fn wait_until(a: &AtomicU32, expected: u32, deadline: Instant) -> bool {
+  let timeout = deadline.saturating_duration_since(Instant::now());
+  if timeout.is_zero() {
+    return false;
+  }
+  wait_timeout(a, expected, timeout)
+}
+
+/// `atomic_wait::wait`, or [`wait_until`] if there's a deadline.
+pub fn wait_deadline(a: &AtomicU32, expected: u32, deadline: Option<Instant>) -> bool {
+  match deadline {
+    None => {
+      atomic_wait::wait(a, expected);
+      true
+    }
+    Some(deadline) => wait_until(a, expected, deadline),
+  }
+}
+
+/// Like [`wait_until`], but with a relative timeout.
+pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) -> bool {
+  let ts = libc::timespec {
+    tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+    tv_nsec: timeout.subsec_nanos() as _,
+  };
+  let r = unsafe {
+    libc::syscall(
+      libc::SYS_futex,
+      a as *const AtomicU32,
+      libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+      expected,
+      &ts as *const libc::timespec,
+    )
+  };
+  !(r < 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::ETIMEDOUT))
+}
+
+/// Wakes at most one thread waiting on `a`, and tells whether there was one.
+pub fn wake_one(a: &AtomicU32) -> bool {
+  let r = unsafe {
+    libc::syscall(
+      libc::SYS_futex,
+      a as *const AtomicU32,
+      libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+      1i32,
+    )
+  };
+  r > 0
+}
+
+/// Wakes one thread waiting on `from` and moves all others to wait on `to`
+/// instead, if `from` still holds `expected`. Returns false if it didn't.
+pub fn requeue_one(from: &AtomicU32, expected: u32, to: *const AtomicU32) -> bool {
+  let r = unsafe {
+    libc::syscall(
+      libc::SYS_futex,
+      from as *const AtomicU32,
+      libc::FUTEX_CMP_REQUEUE | libc::FUTEX_PRIVATE_FLAG,
+      1i32,
+      // The maximum number to requeue, passed in place of a timeout.
+      i32::MAX as usize,
+      to,
+      expected,
+    )
+  };
+  !(r < 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::EAGAIN))
+}
@@ -0,0 +1,335 @@
+// A lock order checker in the spirit of Linux's lockdep, behind the
+// `lockdep` feature. Every lock gets a class id on first use, every thread
+// keeps a stack of the classes it holds, and every blocking acquisition
+// records "held before" edges in a global graph. An acquisition that would
+// close a cycle in that graph can deadlock, given the right timing, so it
+// panics instead, whether or not it would actually deadlock this time.
+//
+// Unlike Linux, a class is a single lock instance rather than all locks
+// initialized at the same place. A lock's class leaves the graph when the
+// lock is dropped, so the graph only ever holds locks that still exist.
+//
+// Every lock in `lock` reports here; the pthread wrappers in `primitive`
+// don't.
+//
+// Without the feature, everything here compiles to nothing.
+
+#[cfg(feature = "lockdep")]
+mod imp {
+  use std::{
+    backtrace::Backtrace,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    sync::{
+      LazyLock, Mutex,
+      atomic::{AtomicUsize, Ordering},
+    },
+  };
+
+  pub struct Class(AtomicUsize);
+
+  impl Class {
+    pub const fn new() -> Self {
+      Self(AtomicUsize::new(0))
+    }
+
+    pub(super) fn id(&self) -> usize {
+      static NEXT: AtomicUsize = AtomicUsize::new(1);
+      let id = self.0.load(Ordering::Relaxed);
+      if id != 0 {
+        return id;
+      }
+      let new = NEXT.fetch_add(1, Ordering::Relaxed);
+      match self
+        .0
+        .compare_exchange(0, new, Ordering::Relaxed, Ordering::Relaxed)
+      {
+        Ok(_) => new,
+        Err(id) => id,
+      }
+    }
+  }
+
+  impl Drop for Class {
+    fn drop(&mut self) {
+      let id = *self.0.get_mut();
+      // Never used, so never in the graph.
+      if id == 0 {
+        return;
+      }
+      let mut graph = GRAPH.lock().unwrap_or_else(|e| e.into_inner());
+      graph.remove(&id);
+      graph.retain(|_, edges| {
+        edges.remove(&id);
+        !edges.is_empty()
+      });
+    }
+  }
+
+  // For every class, the classes acquired while holding it, with where
+  // that first happened. A std Mutex, as our own locks report to us.
+  pub(super) static GRAPH: LazyLock<Mutex<HashMap<usize, HashMap<usize, Backtrace>>>> =
+    LazyLock::new(Default::default);
+
+  thread_local! {
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+  }
+
+  // A path from `from` to `to` in the graph, as the list of its edges.
+  fn path(
+    graph: &HashMap<usize, HashMap<usize, Backtrace>>,
+    from: usize,
+    to: usize,
+  ) -> Option<Vec<(usize, usize)>> {
+    let mut seen = HashSet::new();
+    let mut stack = vec![(from, Vec::new())];
+    while let Some((class, edges)) = stack.pop() {
+      if class == to {
+        return Some(edges);
+      }
+      if !seen.insert(class) {
+        continue;
+      }
+      for &next in graph.get(&class).into_iter().flat_map(|e| e.keys()) {
+        let mut edges = edges.clone();
+        edges.push((class, next));
+        stack.push((next, edges));
+      }
+    }
+    None
+  }
+
+  pub fn acquire(class: &Class) {
+    let id = class.id();
+    // Not available anymore while the thread's locals are being torn down.
+    let Ok(held) = HELD.try_with(|held| held.borrow().clone()) else {
+      return;
+    };
+    let mut graph = GRAPH.lock().unwrap_or_else(|e| e.into_inner());
+    for &h in &held {
+      // Taking the same lock again is up to the lock itself.
+      if h == id || graph.get(&h).is_some_and(|e| e.contains_key(&id)) {
+        continue;
+      }
+      if let Some(edges) = path(&graph, id, h) {
+        let mut msg = format!(
+          "lock order inversion: acquiring lock #{id} while holding lock #{h}, \
+           but they were taken in the opposite order before:\n"
+        );
+        for (a, b) in edges {
+          msg += &format!("\nlock #{b} acquired while holding lock #{a} at:\n");
+          msg += &graph[&a][&b].to_string();
+        }
+        msg += &format!("\nlock #{id} now acquired while holding lock #{h} at:\n");
+        msg += &Backtrace::force_capture().to_string();
+        drop(graph);
+        panic!("{msg}");
+      }
+      graph
+        .entry(h)
+        .or_default()
+        .insert(id, Backtrace::force_capture());
+    }
+  }
+
+  pub fn acquired(class: &Class) {
+    let id = class.id();
+    let _ = HELD.try_with(|held| held.borrow_mut().push(id));
+  }
+
+  pub fn release(class: &Class) {
+    let id = class.id();
+    let _ = HELD.try_with(|held| {
+      let mut held = held.borrow_mut();
+      // Not necessarily the last one: guards can be dropped in any order.
+      if let Some(i) = held.iter().rposition(|&h| h == id) {
+        held.remove(i);
+      }
+    });
+  }
+}
+
+#[cfg(not(feature = "lockdep"))]
+mod imp {
+  pub struct Class;
+
+  impl Class {
+    pub const fn new() -> Self {
+      Self
+    }
+  }
+
+  #[inline(always)]
+  pub fn acquire(_class: &Class) {}
+
+  #[inline(always)]
+  pub fn acquired(_class: &Class) {}
+
+  #[inline(always)]
+  pub fn release(_class: &Class) {}
+}
+
+// Locks call `acquire` before blocking on a lock, which records the order
+// and may panic, `acquired` once they hold it, and `release` when they
+// unlock. Non-blocking attempts can't deadlock, so they skip `acquire`
+// and don't order anything.
+pub use imp::{Class, acquire, acquired, release};
+
+#[cfg(all(test, feature = "lockdep"))]
+mod tests {
+  use super::*;
+  use crate::lock::condvar::Condvar;
+  use crate::lock::mutex::Mutex;
+  use crate::lock::reentrant::ReentrantMutex;
+  use crate::lock::rwlock::RwLock;
+  use crate::lock::seqlock::SeqLock;
+  use crate::lock::sharded::ShardedRwLock;
+  use crate::lock::spin::SpinLock;
+  use crate::lock::ticket::TicketLock;
+  use std::{panic, time::Duration};
+
+  // Runs `f`, which should panic on a lock order inversion.
+  fn assert_inversion(f: impl FnOnce()) {
+    let err = panic::catch_unwind(panic::AssertUnwindSafe(f)).unwrap_err();
+    let msg = err.downcast_ref::<String>().unwrap();
+    assert!(msg.starts_with("lock order inversion"), "{msg}");
+  }
+
+  #[test]
+  fn test_mutex_inversion() {
+    let a = Mutex::new(0);
+    let b = Mutex::new(0);
+    {
+      let _a = a.lock();
+      let _b = b.lock();
+    }
+    // Never actually deadlocks on a single thread, but could with two.
+    assert_inversion(|| {
+      let _b = b.lock();
+      let _a = a.lock();
+    });
+    // The panicking thread released what it held.
+    let _a = a.lock();
+    let _b = b.lock();
+  }
+
+  #[test]
+  fn test_transitive_inversion() {
+    let a = Mutex::new(0);
+    let b = RwLock::new(0);
+    let c = Mutex::new(0);
+    {
+      let _a = a.lock();
+      let _b = b.read();
+    }
+    {
+      let _b = b.write();
+      let _c = c.lock();
+    }
+    assert_inversion(|| {
+      let _c = c.lock();
+      let _a = a.lock();
+    });
+  }
+
+  #[test]
+  fn test_spin_lock_inversion() {
+    let a = SpinLock::new(0);
+    let b = TicketLock::new(0);
+    {
+      let _a = a.lock();
+      let _b = b.lock();
+    }
+    assert_inversion(|| {
+      let _b = b.lock();
+      let _a = a.lock();
+    });
+  }
+
+  #[test]
+  fn test_try_lock() {
+    let a = Mutex::new(0);
+    let b = Mutex::new(0);
+    {
+      let _a = a.lock();
+      let _b = b.try_lock().unwrap();
+    }
+    // Doesn't count: a try_lock can't deadlock.
+    let _b = b.lock();
+    let _a = a.lock();
+  }
+
+  #[test]
+  fn test_condvar_wait() {
+    let a = Mutex::new(0);
+    let b = Mutex::new(0);
+    let condvar = Condvar::new();
+    {
+      let g = a.lock();
+      // Releases and reacquires `a`, and must leave it held just once.
+      let (g, _) = condvar.wait_timeout(g, Duration::from_millis(1));
+      drop(g);
+    }
+    {
+      let _b = b.lock();
+      let _a = a.lock();
+    }
+    // Relocking `b` in `wait` while holding `a` counts like `lock` does.
+    assert_inversion(|| {
+      let g = b.lock();
+      let _a = a.lock();
+      drop(condvar.wait_timeout(g, Duration::from_millis(1)));
+    });
+  }
+
+  #[test]
+  fn test_reentrant_inversion() {
+    let a = ReentrantMutex::new(0);
+    let b = ShardedRwLock::new(0);
+    {
+      let _a = a.lock();
+      let _b = b.read();
+      // Relocking what the thread holds is no inversion.
+      let _a2 = a.lock();
+    }
+    assert_inversion(|| {
+      let _b = b.write();
+      let _a = a.lock();
+    });
+  }
+
+  #[test]
+  fn test_seqlock_read_inversion() {
+    let a = Mutex::new(0);
+    let b = SeqLock::new(0);
+    b.update(|_| drop(a.lock()));
+    // `read` holds nothing afterwards, but may wait for a writer that
+    // is about to lock `a`.
+    assert_inversion(|| {
+      let _a = a.lock();
+      b.read();
+    });
+  }
+
+  #[test]
+  fn test_dropped_lock_leaves_graph() {
+    let a = Class::new();
+    let b = Class::new();
+    acquire(&a);
+    acquired(&a);
+    acquire(&b);
+    acquired(&b);
+    release(&b);
+    release(&a);
+    let (a_id, b_id) = (a.id(), b.id());
+    let has_edge = || {
+      let graph = imp::GRAPH.lock().unwrap();
+      graph.get(&a_id).is_some_and(|e| e.contains_key(&b_id))
+    };
+    assert!(has_edge());
+    drop(b);
+    assert!(!has_edge());
+    drop(a);
+    assert!(!imp::GRAPH.lock().unwrap().contains_key(&a_id));
+  }
+}
@@ -0,0 +1,191 @@
+use std::{
+  cell::UnsafeCell,
+  marker::PhantomPinned,
+  ops::{Deref, DerefMut},
+  pin::Pin,
+  ptr,
+  sync::atomic::{
+    AtomicBool, AtomicPtr,
+    Ordering::{AcqRel, Acquire, Relaxed, Release},
+  },
+};
+
+use super::backoff::{Backoff, SpinPolicy};
+use super::lockdep;
+
+// A waiter's place in the queue of an `McsLock`, usually on the waiter's
+// stack. Other threads hold pointers to it while it is queued, which is
+// why it must be pinned (see `pin.rs`).
+pub struct McsNode {
+  next: AtomicPtr<McsNode>,
+  // Whether the owner still has to wait. Set by the predecessor.
+  waiting: AtomicBool,
+  // Whether the node is linked into a queue.
+  queued: AtomicBool,
+  _pin: PhantomPinned,
+}
+
+impl McsNode {
+  pub const fn new() -> Self {
+    McsNode {
+      next: AtomicPtr::new(ptr::null_mut()),
+      waiting: AtomicBool::new(false),
+      queued: AtomicBool::new(false),
+      _pin: PhantomPinned,
+    }
+  }
+}
+
+impl Default for McsNode {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Drop for McsNode {
+  fn drop(&mut self) {
+    // Only possible by leaking the guard. Other threads would write to
+    // the freed node, and unwinding can't help with that.
+    if self.queued.load(Relaxed) {
+      std::process::abort();
+    }
+  }
+}
+
+// A FIFO spin lock where every waiter spins on its own node, so a handoff
+// only touches the cache lines of two threads.
+//
+//   let node = pin!(McsNode::new());
+//   *lock.lock(node) += 1;
+pub struct McsLock<T> {
+  tail: AtomicPtr<McsNode>,
+  spin: SpinPolicy,
+  class: lockdep::Class,
+  value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for McsLock<T> where T: Send {}
+
+pub struct Guard<'a, T> {
+  lock: &'a McsLock<T>,
+  node: Pin<&'a mut McsNode>,
+}
+
+impl<T> McsLock<T> {
+  pub const fn new(value: T) -> Self {
+    McsLock {
+      tail: AtomicPtr::new(ptr::null_mut()),
+      // As for `TicketLock`: yield eventually.
+      spin: SpinPolicy::exponential(),
+      class: lockdep::Class::new(),
+      value: UnsafeCell::new(value),
+    }
+  }
+
+  pub const fn spin_policy(mut self, spin: SpinPolicy) -> Self {
+    self.spin = spin;
+    self
+  }
+
+  pub fn lock<'a>(&'a self, node: Pin<&'a mut McsNode>) -> Guard<'a, T> {
+    // Still queued after leaking its guard, as in `McsNode::drop`: other
+    // threads may be about to write to it, so it can't be reset.
+    if node.queued.load(Relaxed) {
+      std::process::abort();
+    }
+    lockdep::acquire(&self.class);
+    let me = &*node as *const McsNode as *mut McsNode;
+    node.next.store(ptr::null_mut(), Relaxed);
+    node.waiting.store(true, Relaxed);
+    node.queued.store(true, Relaxed);
+    // Release: a successor finds our initialized node through `tail`.
+    let prev = self.tail.swap(me, AcqRel);
+    if !prev.is_null() {
+      // The predecessor won't leave before it has seen this.
+      unsafe { (*prev).next.store(me, Release) };
+      let mut backoff = Backoff::new(&self.spin);
+      while node.waiting.load(Acquire) {
+        backoff.snooze();
+      }
+      backoff.finish(true);
+    }
+    lockdep::acquired(&self.class);
+    Guard { lock: self, node }
+  }
+
+  fn unlock(&self, node: &McsNode) {
+    lockdep::release(&self.class);
+    let me = node as *const McsNode as *mut McsNode;
+    let mut next = node.next.load(Acquire);
+    if next.is_null() {
+      if self
+        .tail
+        .compare_exchange(me, ptr::null_mut(), Release, Relaxed)
+        .is_ok()
+      {
+        node.queued.store(false, Relaxed);
+        return;
+      }
+      // A successor has swapped itself in, but not linked itself yet.
+      let mut backoff = Backoff::new(&self.spin);
+      loop {
+        next = node.next.load(Acquire);
+        if !next.is_null() {
+          break;
+        }
+        backoff.snooze();
+      }
+    }
+    node.queued.store(false, Relaxed);
+    // The successor may return and free its node right after this.
+    unsafe { (*next).waiting.store(false, Release) };
+  }
+}
+
+unsafe impl<T: Send> Send for Guard<'_, T> {}
+unsafe impl<T: Sync> Sync for Guard<'_, T> {}
+
+impl<T> Deref for Guard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.lock.value.get() }
+  }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    unsafe { &mut *self.lock.value.get() }
+  }
+}
+
+impl<T> Drop for Guard<'_, T> {
+  fn drop(&mut self) {
+    self.lock.unlock(&self.node);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{pin::pin, thread};
+
+  #[test]
+  fn test_mcs_lock() {
+    let l = McsLock::new(0);
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          // The same node can be used again once the guard is gone.
+          let mut node = pin!(McsNode::new());
+          for _ in 0..10_000 {
+            *l.lock(node.as_mut()) += 1;
+          }
+        });
+      }
+    });
+    let node = pin!(McsNode::new());
+    assert_eq!(*l.lock(node), 40_000);
+    assert!(l.tail.load(Relaxed).is_null());
+  }
+}
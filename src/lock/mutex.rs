@@ -1,90 +1,519 @@
 use std::{
-  cell::UnsafeCell,
+  marker::PhantomData,
+  mem::ManuallyDrop,
   ops::{Deref, DerefMut},
-  sync::atomic::{AtomicU32, Ordering},
+  ptr,
+  sync::{
+    OnceLock,
+    atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+  },
+  thread,
+  time::{Duration, Instant},
 };
 
-use atomic_wait::{wait, wake_all, wake_one};
+use atomic_wait::{wait, wake_one};
+
+use super::backoff::{Backoff, SpinPolicy};
+use super::futex;
+use super::lockdep;
+use super::poison::{Flag, LockResult};
+use super::raw::{self, GuardSend, RawMutex};
 
 pub struct Mutex<T> {
-  // 0： unlocked
-  // 1: locked, no other thread waiting
-  // 2: locked, other threads are waiting
-  state: AtomicU32,
-  value: UnsafeCell<T>,
+  inner: raw::Mutex<RawFutexMutex, T>,
+  // Who holds the lock, in debug builds.
+  owner: Owner,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fairness {
+  // Unlocking lets anyone grab the lock, even if a waiter was just woken.
+  // Best throughput, but a waiter can be passed over indefinitely.
+  Unfair,
+  // Like `Unfair`, but a contended unlock hands the lock to a waiter
+  // at least once per the given period, like parking_lot does.
+  Eventual(Duration),
+  // Every contended unlock hands the lock to a waiter.
+  Always,
+}
+
+fn start_nanos() -> u64 {
+  static START: OnceLock<Instant> = OnceLock::new();
+  START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+// A nonzero id for the current thread. Unlike the address of a thread
+// local, never reused by a later thread, which could otherwise inherit a
+// lock leaked by a thread that exited.
+pub(super) fn current_thread() -> usize {
+  static NEXT: AtomicUsize = AtomicUsize::new(1);
+  thread_local!(static ID: usize = NEXT.fetch_add(1, Ordering::Relaxed));
+  ID.with(|id| *id)
+}
+
+// How long a `lock()` may wait before debug builds report who it's
+// waiting for.
+const SLOW_WAIT: Duration = Duration::from_secs(1);
+
+// Debug builds remember the thread holding a `Mutex` and since when, to
+// catch a thread locking it twice, which would otherwise hang forever,
+// and to tell waiters that wait suspiciously long what they wait for.
+#[cfg(debug_assertions)]
+struct Owner {
+  // `current_thread()` of the holder, or 0.
+  thread: AtomicUsize,
+  // In nanoseconds since `start_nanos`'s epoch.
+  since: AtomicU64,
+}
+
+#[cfg(debug_assertions)]
+impl Owner {
+  const fn new() -> Self {
+    Self {
+      thread: AtomicUsize::new(0),
+      since: AtomicU64::new(0),
+    }
+  }
+
+  fn check_relock(&self) {
+    if self.thread.load(Ordering::Relaxed) == current_thread() {
+      panic!("deadlock: Mutex locked again by the thread holding it");
+    }
+  }
+
+  fn acquired(&self) {
+    self.since.store(start_nanos(), Ordering::Relaxed);
+    self.thread.store(current_thread(), Ordering::Relaxed);
+  }
+
+  fn released(&self) {
+    if self.thread.swap(0, Ordering::Relaxed) != current_thread() {
+      panic!("Mutex unlocked by a thread not holding it");
+    }
+  }
+
+  // Racy: the lock may change hands while we look.
+  fn held_for(&self) -> Option<(usize, Duration)> {
+    let thread = self.thread.load(Ordering::Relaxed);
+    let since = self.since.load(Ordering::Relaxed);
+    let held = start_nanos().saturating_sub(since);
+    (thread != 0).then(|| (thread, Duration::from_nanos(held)))
+  }
+
+  fn report_slow_wait(&self, waited: Duration) {
+    match self.held_for() {
+      Some((thread, held)) => eprintln!(
+        "thread {} waited {:?} for a Mutex, held by thread {} for {:?}",
+        current_thread(),
+        waited,
+        thread,
+        held
+      ),
+      None => eprintln!(
+        "thread {} waited {:?} for a Mutex",
+        current_thread(),
+        waited
+      ),
+    }
+  }
 }
 
-unsafe impl<T: Send> Sync for Mutex<T> {}
+// Nothing to keep track of in release builds.
+#[cfg(not(debug_assertions))]
+struct Owner;
+
+#[cfg(not(debug_assertions))]
+impl Owner {
+  const fn new() -> Self {
+    Self
+  }
+
+  #[inline(always)]
+  fn check_relock(&self) {}
+
+  #[inline(always)]
+  fn acquired(&self) {}
+
+  #[inline(always)]
+  fn released(&self) {}
+
+  #[inline(always)]
+  fn report_slow_wait(&self, _waited: Duration) {}
+}
 
 impl<T> Mutex<T> {
   #[inline]
   pub const fn new(val: T) -> Self {
     Self {
-      state: AtomicU32::new(0),
-      value: UnsafeCell::new(val),
+      inner: raw::Mutex::new(val),
+      owner: Owner::new(),
     }
   }
 
+  pub const fn fairness(mut self, fairness: Fairness) -> Self {
+    self.inner.raw = self.inner.raw.fairness(fairness);
+    self
+  }
+
+  pub const fn spin_policy(mut self, spin: SpinPolicy) -> Self {
+    self.inner.raw = self.inner.raw.spin_policy(spin);
+    self
+  }
+
   pub fn lock(&self) -> MutexGuard<'_, T> {
-    // while self.state.swap(1, Ordering::Acquire) == 1 {
-    //   wait(&self.state, 1);
-    // }
-    if self
-      .state
-      .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
-      .is_err()
-    {
-      //while self.state.swap(2, Ordering::Acquire) != 0 {
-      // wait(&self.state, 2);
-      //}
-      // The lock was already contended
-      lock_contended(&self.state);
+    self.owner.check_relock();
+    let guard = self.inner.lock_with(|raw| {
+      if !raw.try_lock() {
+        // The lock was already contended
+        self.lock_slow(raw);
+      }
+    });
+    self.guard(guard)
+  }
+
+  fn lock_slow(&self, raw: &RawFutexMutex) {
+    if cfg!(debug_assertions) {
+      if raw.lock_until(Instant::now() + SLOW_WAIT) {
+        return;
+      }
+      self.owner.report_slow_wait(SLOW_WAIT);
+    }
+    raw.lock();
+  }
+
+  fn guard<'a>(&'a self, guard: raw::MutexGuard<'a, RawFutexMutex, T>) -> MutexGuard<'a, T> {
+    self.owner.acquired();
+    MutexGuard {
+      mutex: self,
+      guard,
+      _marker: PhantomData,
+    }
+  }
+
+  pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+    self.inner.try_lock().map(|g| self.guard(g))
+  }
+
+  pub fn try_lock_for(&self, timeout: Duration) -> Option<MutexGuard<'_, T>> {
+    match Instant::now().checked_add(timeout) {
+      Some(deadline) => self.try_lock_until(deadline),
+      // A timeout that large is as good as forever.
+      None => Some(self.lock()),
+    }
+  }
+
+  pub fn try_lock_until(&self, deadline: Instant) -> Option<MutexGuard<'_, T>> {
+    self
+      .inner
+      .try_lock_with(|raw| raw.lock_until(deadline))
+      .map(|g| self.guard(g))
+  }
+
+  // Locks for a thread that was just woken by a `Condvar`. It may have been
+  // requeued onto our futex along with others, which only get woken by
+  // unlocking from state 2, so it must lock with state 2.
+  pub(super) fn relock(&self) -> MutexGuard<'_, T> {
+    let guard = self.inner.lock_with(|raw| {
+      lock_wait(&raw.state, true, None);
+    });
+    self.guard(guard)
+  }
+
+  pub(super) fn futex(&self) -> &AtomicU32 {
+    &self.inner.raw.state
+  }
+
+  pub(super) fn class(&self) -> &lockdep::Class {
+    self.inner.class()
+  }
+}
+
+// Returns false only if `deadline` passed before the lock was acquired.
+fn lock_contended(state: &AtomicU32, spin: &SpinPolicy, deadline: Option<Instant>) -> bool {
+  let mut backoff = Backoff::new(spin);
+  while state.load(Ordering::Relaxed) == 1 && backoff.spin() {}
+  let acquired = state
+    .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+    .is_ok();
+  backoff.finish(acquired);
+  acquired || lock_wait(state, false, deadline)
+}
+
+// The sleeping part of `lock_contended`, which always locks with state 2.
+// `woken` tells whether we have slept on the futex before. Only such a
+// thread may take over a handed off lock, so newcomers can't barge past
+// the waiters that a fair unlock meant to serve.
+fn lock_wait(state: &AtomicU32, mut woken: bool, deadline: Option<Instant>) -> bool {
+  loop {
+    let s = state.load(Ordering::Relaxed);
+    match s {
+      // Lock it, or mark it as contended before going to sleep, so the
+      // unlock will wake us. Either way with 2, as there may be others.
+      0 | 1 => {
+        if state
+          .compare_exchange(s, 2, Ordering::Acquire, Ordering::Relaxed)
+          .is_err()
+        {
+          continue;
+        }
+        if s == 0 {
+          return true;
+        }
+      }
+      3 if woken => {
+        if state
+          .compare_exchange(3, 2, Ordering::Acquire, Ordering::Relaxed)
+          .is_ok()
+        {
+          return true;
+        }
+        continue;
+      }
+      _ => {}
     }
-    MutexGuard { mutex: self }
+
+    let expected = if s == 3 { 3 } else { 2 };
+    match deadline {
+      None => wait(state, expected),
+      Some(deadline) => {
+        if !futex::wait_until(state, expected, deadline) {
+          // A timed out waiter was not woken, so it didn't consume a
+          // wake_one meant for someone else. It leaves the state at 2,
+          // which only costs the next unlock a (possibly spurious) wake,
+          // and that unlock resets the state to 0 again.
+          // One last try, in case the lock was released in the meantime.
+          return state
+            .compare_exchange(0, 2, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        }
+      }
+    }
+    woken = true;
   }
 }
 
-fn lock_contended(state: &AtomicU32) {
-  let mut spin_count = 0;
-  while state.load(Ordering::Relaxed) == 1 && spin_count < 100 {
-    std::hint::spin_loop();
-    spin_count += 1;
+fn unlock(state: &AtomicU32) {
+  if state.swap(0, Ordering::Release) == 2 {
+    wake_one(state);
   }
+}
+
+fn unlock_fair(state: &AtomicU32) {
   if state
-    .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+    .compare_exchange(1, 0, Ordering::Release, Ordering::Relaxed)
     .is_ok()
   {
     return;
   }
-
-  while state.swap(2, Ordering::Acquire) != 0 {
-    wait(state, 2);
+  // There are waiters: don't unlock, but hand the lock to the one we wake.
+  state.store(3, Ordering::Release);
+  if futex::wake_one(state) {
+    return;
+  }
+  // Nobody was asleep yet, so unlock after all. Unless a waiter took
+  // it over already, wake whoever went to sleep on 3 in the meantime.
+  if state
+    .compare_exchange(3, 0, Ordering::Release, Ordering::Relaxed)
+    .is_ok()
+  {
+    wake_one(state);
   }
 }
 
 pub struct MutexGuard<'a, T> {
   pub(super) mutex: &'a Mutex<T>,
+  guard: raw::MutexGuard<'a, RawFutexMutex, T>,
+  // Must be dropped on the thread that locked, like std's, which is what
+  // makes tracking the owning thread possible.
+  _marker: PhantomData<*const ()>,
 }
 
+unsafe impl<T: Sync> Sync for MutexGuard<'_, T> {}
+
 impl<T> Deref for MutexGuard<'_, T> {
   type Target = T;
   fn deref(&self) -> &Self::Target {
-    unsafe { &*self.mutex.value.get() }
+    &self.guard
   }
 }
 
 impl<T> DerefMut for MutexGuard<'_, T> {
   fn deref_mut(&mut self) -> &mut Self::Target {
-    unsafe { &mut *self.mutex.value.get() }
+    &mut self.guard
+  }
+}
+
+impl<T> MutexGuard<'_, T> {
+  // Unlocks, handing the lock directly to a waiter if there is one,
+  // whatever the mutex's `Fairness`.
+  pub fn unlock_fair(self) {
+    let this = ManuallyDrop::new(self);
+    this.mutex.owner.released();
+    // Not dropped, so not unlocked the usual way.
+    let guard = unsafe { ptr::read(&this.guard) };
+    unlock_fair(&guard.into_raw().raw.state);
   }
 }
 
 impl<T> Drop for MutexGuard<'_, T> {
   fn drop(&mut self) {
-    if self.mutex.state.swap(0, Ordering::Release) == 2 {
-      wake_one(&self.mutex.state);
+    // Then dropping `guard` unlocks.
+    self.mutex.owner.released();
+  }
+}
+
+// The futex state machine under `Mutex`, without data or owner tracking.
+// Also usable on its own through `raw::Mutex`, or as a building block.
+pub struct RawFutexMutex {
+  // 0： unlocked
+  // 1: locked, no other thread waiting
+  // 2: locked, other threads are waiting
+  // 3: unlocked, but handed off to a waiter by a fair unlock
+  state: AtomicU32,
+  fairness: Fairness,
+  spin: SpinPolicy,
+  // When the next fair unlock is due, for `Fairness::Eventual`,
+  // in nanoseconds since `start_nanos`'s epoch.
+  next_fair: AtomicU64,
+}
+
+impl RawFutexMutex {
+  pub const fn new() -> Self {
+    Self {
+      state: AtomicU32::new(0),
+      fairness: Fairness::Unfair,
+      spin: SpinPolicy::DEFAULT,
+      next_fair: AtomicU64::new(0),
+    }
+  }
+
+  pub const fn fairness(mut self, fairness: Fairness) -> Self {
+    self.fairness = fairness;
+    self
+  }
+
+  pub const fn spin_policy(mut self, spin: SpinPolicy) -> Self {
+    self.spin = spin;
+    self
+  }
+
+  // Returns false if `deadline` passed before the lock was acquired.
+  pub fn lock_until(&self, deadline: Instant) -> bool {
+    RawMutex::try_lock(self) || lock_contended(&self.state, &self.spin, Some(deadline))
+  }
+
+  fn fair_unlock_due(&self) -> bool {
+    match self.fairness {
+      Fairness::Unfair => false,
+      Fairness::Always => true,
+      Fairness::Eventual(period) => {
+        // Only worth looking at the clock if someone is waiting.
+        if self.state.load(Ordering::Relaxed) != 2 {
+          return false;
+        }
+        let now = start_nanos();
+        if now < self.next_fair.load(Ordering::Relaxed) {
+          return false;
+        }
+        self.next_fair.store(
+          now.saturating_add(period.as_nanos() as u64),
+          Ordering::Relaxed,
+        );
+        true
+      }
+    }
+  }
+}
+
+impl Default for RawFutexMutex {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+unsafe impl RawMutex for RawFutexMutex {
+  const INIT: Self = Self::new();
+
+  type GuardMarker = GuardSend;
+
+  fn lock(&self) {
+    if !RawMutex::try_lock(self) {
+      lock_contended(&self.state, &self.spin, None);
+    }
+  }
+
+  fn try_lock(&self) -> bool {
+    self
+      .state
+      .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+      .is_ok()
+  }
+
+  unsafe fn unlock(&self) {
+    if self.fair_unlock_due() {
+      unlock_fair(&self.state);
+    } else {
+      unlock(&self.state);
+    }
+  }
+}
+
+// Opt-in poisoning on top of `Mutex`, which itself stays poison-free.
+pub struct PoisonMutex<T> {
+  inner: Mutex<T>,
+  poison: Flag,
+}
+
+impl<T> PoisonMutex<T> {
+  pub const fn new(val: T) -> Self {
+    Self {
+      inner: Mutex::new(val),
+      poison: Flag::new(),
     }
   }
+
+  pub fn lock(&self) -> LockResult<PoisonMutexGuard<'_, T>> {
+    let guard = self.inner.lock();
+    self.poison.result(PoisonMutexGuard {
+      guard,
+      poison: &self.poison,
+      panicking: thread::panicking(),
+    })
+  }
+
+  pub fn is_poisoned(&self) -> bool {
+    self.poison.get()
+  }
+
+  pub fn clear_poison(&self) {
+    self.poison.clear();
+  }
+}
+
+pub struct PoisonMutexGuard<'a, T> {
+  guard: MutexGuard<'a, T>,
+  poison: &'a Flag,
+  panicking: bool,
+}
+
+impl<T> Deref for PoisonMutexGuard<'_, T> {
+  type Target = T;
+  fn deref(&self) -> &Self::Target {
+    &self.guard
+  }
+}
+
+impl<T> DerefMut for PoisonMutexGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.guard
+  }
+}
+
+impl<T> Drop for PoisonMutexGuard<'_, T> {
+  fn drop(&mut self) {
+    // Runs before `guard` is dropped, i.e. while still holding the lock.
+    self.poison.done(self.panicking);
+  }
 }
 
 #[cfg(test)]
@@ -96,7 +525,7 @@ mod tests {
 
   #[test]
   fn test_mutex() {
-    let mut m = Mutex::new(5);
+    let m = Mutex::new(5);
     *m.lock() += 1;
     *m.lock() += 2;
     thread::scope(|s| {
@@ -108,6 +537,205 @@ mod tests {
     dbg!(*m.lock());
   }
 
+  #[test]
+  fn test_try_lock() {
+    let m = Mutex::new(0);
+    let g = m.lock();
+    assert!(m.try_lock().is_none());
+    assert!(m.try_lock_for(Duration::from_millis(10)).is_none());
+    assert!(m.try_lock_until(Instant::now()).is_none());
+    drop(g);
+    *m.try_lock().unwrap() += 1;
+    *m.try_lock_for(Duration::from_millis(10)).unwrap() += 1;
+    assert_eq!(*m.lock(), 2);
+    assert_eq!(m.futex().load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn test_lock_timeout_no_lost_wakeup() {
+    let m = Mutex::new(0);
+    let g = m.lock();
+    thread::scope(|s| {
+      // This waiter gives up and leaves the state at 2.
+      s.spawn(|| {
+        let start = Instant::now();
+        assert!(m.try_lock_for(Duration::from_millis(50)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+      })
+      .join()
+      .unwrap();
+      assert_eq!(m.futex().load(Ordering::Relaxed), 2);
+
+      // A blocking waiter must still be woken by the unlock below.
+      s.spawn(|| *m.lock() += 1);
+      thread::sleep(Duration::from_millis(100));
+      drop(g);
+    });
+    assert_eq!(*m.lock(), 1);
+    assert_eq!(m.futex().load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn test_lock_timeout_acquires_after_release() {
+    let m = Mutex::new(0);
+    thread::scope(|s| {
+      let g = m.lock();
+      let t = s.spawn(|| {
+        *m.try_lock_for(Duration::from_secs(10)).unwrap() += 1;
+      });
+      thread::sleep(Duration::from_millis(50));
+      drop(g);
+      t.join().unwrap();
+    });
+    assert_eq!(*m.lock(), 1);
+    assert_eq!(m.futex().load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn test_poison() {
+    let m = PoisonMutex::new(0);
+    *m.lock().unwrap() += 1;
+    assert!(!m.is_poisoned());
+
+    let r = thread::scope(|s| {
+      s.spawn(|| {
+        let mut g = m.lock().unwrap();
+        *g += 1;
+        panic!("half updated");
+      })
+      .join()
+    });
+    assert!(r.is_err());
+    assert!(m.is_poisoned());
+
+    let mut g = m.lock().err().unwrap().into_inner();
+    assert_eq!(*g, 2);
+    *g = 0;
+    drop(g);
+    // Still poisoned until explicitly cleared.
+    assert!(m.lock().is_err());
+    m.clear_poison();
+    assert_eq!(*m.lock().unwrap(), 0);
+  }
+
+  #[test]
+  fn test_unlock_fair() {
+    let m = Mutex::new(0);
+    let mut g = m.lock();
+    *g += 1;
+    g.unlock_fair();
+    assert_eq!(m.futex().load(Ordering::Relaxed), 0);
+
+    let m = Mutex::new(0).fairness(Fairness::Always);
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..10_000 {
+            *m.lock() += 1;
+          }
+        });
+      }
+    });
+    assert_eq!(*m.lock(), 40_000);
+    assert_eq!(m.futex().load(Ordering::Relaxed), 0);
+  }
+
+  #[cfg(debug_assertions)]
+  #[test]
+  #[should_panic(expected = "locked again by the thread holding it")]
+  fn test_relock_panics() {
+    let m = Mutex::new(0);
+    let _g = m.lock();
+    let _g2 = m.lock();
+  }
+
+  #[cfg(debug_assertions)]
+  #[test]
+  fn test_slow_wait() {
+    let m = Mutex::new(0);
+    assert!(m.owner.held_for().is_none());
+    thread::scope(|s| {
+      let g = m.lock();
+      s.spawn(|| {
+        // Reports, then keeps waiting.
+        let mut g = m.lock();
+        *g += 1;
+        assert!(m.owner.held_for().is_some());
+      });
+      thread::sleep(SLOW_WAIT + Duration::from_millis(200));
+      let (thread, held) = m.owner.held_for().unwrap();
+      assert_eq!(thread, current_thread());
+      assert!(held > SLOW_WAIT);
+      drop(g);
+    });
+    assert_eq!(*m.lock(), 1);
+    assert!(m.owner.held_for().is_none());
+  }
+
+  // Maximum time any single lock() call waited, per thread.
+  fn max_wait_benchmark(fairness: Fairness) {
+    let m = Mutex::new(0).fairness(fairness);
+    let start = Instant::now();
+    let max_waits: Vec<Duration> = thread::scope(|s| {
+      let threads: Vec<_> = (0..4)
+        .map(|_| {
+          s.spawn(|| {
+            let mut max_wait = Duration::ZERO;
+            for _ in 0..200_000 {
+              let t = Instant::now();
+              let mut g = m.lock();
+              max_wait = max_wait.max(t.elapsed());
+              *g += 1;
+            }
+            max_wait
+          })
+        })
+        .collect();
+      threads.into_iter().map(|t| t.join().unwrap()).collect()
+    });
+    assert_eq!(*m.lock(), 800_000);
+    dbg!(fairness, max_waits, start.elapsed());
+  }
+
+  #[test]
+  fn fairness_benchmark() {
+    max_wait_benchmark(Fairness::Unfair);
+    max_wait_benchmark(Fairness::Eventual(Duration::from_millis(1)));
+    max_wait_benchmark(Fairness::Always);
+  }
+
+  #[test]
+  fn spin_policy_benchmark() {
+    let policies = [
+      ("fixed", SpinPolicy::DEFAULT),
+      ("none", SpinPolicy::NONE),
+      ("exponential", SpinPolicy::exponential()),
+      ("adaptive", SpinPolicy::adaptive()),
+    ];
+    for (name, policy) in policies {
+      let m = Mutex::new(0).spin_policy(policy);
+      std::hint::black_box(&m);
+      let start = Instant::now();
+      for _ in 0..1_000_000 {
+        *m.lock() += 1;
+      }
+      let single = start.elapsed();
+
+      let start = Instant::now();
+      thread::scope(|s| {
+        for _ in 0..4 {
+          s.spawn(|| {
+            for _ in 0..1_000_000 {
+              *m.lock() += 1;
+            }
+          });
+        }
+      });
+      assert_eq!(*m.lock(), 5_000_000);
+      dbg!(name, single, start.elapsed());
+    }
+  }
+
   #[test]
   fn mutex_benckmark() {
     let m = Mutex::new(0);
@@ -0,0 +1,86 @@
+use std::{
+  error::Error,
+  fmt,
+  sync::atomic::{AtomicBool, Ordering},
+  thread,
+};
+
+// Same semantics as std::sync's poisoning: a lock is poisoned when a thread
+// panics while holding it exclusively, and every later locker is told so
+// (but still gets the guard, through `PoisonError::into_inner`).
+
+pub struct PoisonError<G> {
+  guard: G,
+}
+
+pub type LockResult<G> = Result<G, PoisonError<G>>;
+
+impl<G> PoisonError<G> {
+  pub fn new(guard: G) -> Self {
+    PoisonError { guard }
+  }
+
+  pub fn into_inner(self) -> G {
+    self.guard
+  }
+
+  pub fn get_ref(&self) -> &G {
+    &self.guard
+  }
+
+  pub fn get_mut(&mut self) -> &mut G {
+    &mut self.guard
+  }
+}
+
+impl<G> fmt::Debug for PoisonError<G> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("PoisonError").finish_non_exhaustive()
+  }
+}
+
+impl<G> fmt::Display for PoisonError<G> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    "poisoned lock: another task failed inside".fmt(f)
+  }
+}
+
+impl<G> Error for PoisonError<G> {}
+
+pub(crate) struct Flag {
+  failed: AtomicBool,
+}
+
+impl Flag {
+  pub const fn new() -> Self {
+    Flag {
+      failed: AtomicBool::new(false),
+    }
+  }
+
+  // `panicking` is `thread::panicking()` as seen when the guard was
+  // created, so a guard taken during unwinding doesn't poison the lock.
+  // Called from the guard's Drop, before the lock is released, so the
+  // Relaxed store is published by the unlock's Release.
+  pub fn done(&self, panicking: bool) {
+    if !panicking && thread::panicking() {
+      self.failed.store(true, Ordering::Relaxed);
+    }
+  }
+
+  pub fn get(&self) -> bool {
+    self.failed.load(Ordering::Relaxed)
+  }
+
+  pub fn result<G>(&self, guard: G) -> LockResult<G> {
+    if self.get() {
+      Err(PoisonError::new(guard))
+    } else {
+      Ok(guard)
+    }
+  }
+
+  pub fn clear(&self) {
+    self.failed.store(false, Ordering::Relaxed);
+  }
+}
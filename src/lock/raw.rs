@@ -0,0 +1,209 @@
+use std::{
+  cell::UnsafeCell,
+  marker::PhantomData,
+  ops::{Deref, DerefMut},
+};
+
+use super::lockdep;
+
+/// A bare lock without data, to build a `Mutex<R, T>` on, so the same user
+/// code can run over the futex, spin or pthread implementations.
+///
+/// # Safety
+///
+/// Implementations must actually provide mutual exclusion, with
+/// `lock`/`try_lock` acquiring and `unlock` releasing.
+pub unsafe trait RawMutex {
+  // An unlocked mutex.
+  const INIT: Self;
+
+  // `GuardSend`, or `GuardNoSend` if it must be unlocked by the thread
+  // that locked it.
+  type GuardMarker;
+
+  fn lock(&self);
+
+  fn try_lock(&self) -> bool;
+
+  // Safety: only when locked by the current context.
+  unsafe fn unlock(&self);
+}
+
+// Marks a `MutexGuard` as `Send`.
+pub struct GuardSend(());
+
+// Marks a `MutexGuard` as `!Send`.
+pub struct GuardNoSend(*const ());
+
+unsafe impl Sync for GuardNoSend {}
+
+pub struct Mutex<R: RawMutex, T> {
+  pub(super) raw: R,
+  class: lockdep::Class,
+  value: UnsafeCell<T>,
+}
+
+unsafe impl<R: RawMutex + Sync, T: Send> Sync for Mutex<R, T> {}
+
+impl<R: RawMutex, T> Mutex<R, T> {
+  pub const fn new(val: T) -> Self {
+    Self::from_raw(R::INIT, val)
+  }
+
+  // For a raw mutex configured other than `INIT`.
+  pub const fn from_raw(raw: R, val: T) -> Self {
+    Self {
+      raw,
+      class: lockdep::Class::new(),
+      value: UnsafeCell::new(val),
+    }
+  }
+
+  pub fn lock(&self) -> MutexGuard<'_, R, T> {
+    self.lock_with(R::lock)
+  }
+
+  pub fn try_lock(&self) -> Option<MutexGuard<'_, R, T>> {
+    self.try_lock_with(R::try_lock)
+  }
+
+  // Locks with `lock`, for wrappers that lock `raw` in some other way.
+  pub(super) fn lock_with(&self, lock: impl FnOnce(&R)) -> MutexGuard<'_, R, T> {
+    lockdep::acquire(&self.class);
+    lock(&self.raw);
+    self.guard()
+  }
+
+  // Like `lock_with`, for `lock` that may fail. As for `try_lock`, a
+  // failure can't deadlock, so lockdep doesn't look at the lock order.
+  pub(super) fn try_lock_with(
+    &self,
+    lock: impl FnOnce(&R) -> bool,
+  ) -> Option<MutexGuard<'_, R, T>> {
+    lock(&self.raw).then(|| self.guard())
+  }
+
+  fn guard(&self) -> MutexGuard<'_, R, T> {
+    lockdep::acquired(&self.class);
+    MutexGuard {
+      mutex: self,
+      _marker: PhantomData,
+    }
+  }
+
+  // Safety: the lock must be held, and nothing may use the value through
+  // the guard that took it anymore.
+  pub(super) unsafe fn force_unlock(&self) {
+    lockdep::release(&self.class);
+    unsafe { self.raw.unlock() };
+  }
+
+  // For locks built on this one that wait for it without locking it.
+  pub(super) fn class(&self) -> &lockdep::Class {
+    &self.class
+  }
+
+  pub fn into_inner(self) -> T {
+    self.value.into_inner()
+  }
+}
+
+pub struct MutexGuard<'a, R: RawMutex, T> {
+  mutex: &'a Mutex<R, T>,
+  // Send and Sync like `&mut T`, unless the raw mutex says otherwise.
+  _marker: PhantomData<(&'a mut T, R::GuardMarker)>,
+}
+
+impl<'a, R: RawMutex, T> MutexGuard<'a, R, T> {
+  // Gives up the guard without unlocking, for wrappers that unlock `raw`
+  // in some other way.
+  pub(super) fn into_raw(self) -> &'a Mutex<R, T> {
+    let mutex = self.mutex;
+    lockdep::release(&mutex.class);
+    std::mem::forget(self);
+    mutex
+  }
+}
+
+impl<R: RawMutex, T> Deref for MutexGuard<'_, R, T> {
+  type Target = T;
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.mutex.value.get() }
+  }
+}
+
+impl<R: RawMutex, T> DerefMut for MutexGuard<'_, R, T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    unsafe { &mut *self.mutex.value.get() }
+  }
+}
+
+impl<R: RawMutex, T> Drop for MutexGuard<'_, R, T> {
+  fn drop(&mut self) {
+    unsafe { self.mutex.force_unlock() };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lock::mutex::RawFutexMutex;
+  use crate::lock::spin::RawSpinLock;
+  use crate::primitive::mutex::RawPthreadMutex;
+  use std::{thread, time::Instant};
+
+  fn check<R: RawMutex + Sync>() {
+    let m = Mutex::<R, _>::new(0);
+    let g = m.lock();
+    assert!(m.try_lock().is_none());
+    drop(g);
+    *m.try_lock().unwrap() += 1;
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..10_000 {
+            *m.lock() += 1;
+          }
+        });
+      }
+    });
+    assert_eq!(m.into_inner(), 40_001);
+  }
+
+  #[test]
+  fn test_raw_mutex() {
+    check::<RawFutexMutex>();
+    check::<RawSpinLock>();
+    check::<RawPthreadMutex>();
+  }
+
+  fn bench<R: RawMutex + Sync>(name: &str) {
+    let m = Mutex::<R, _>::new(0);
+    std::hint::black_box(&m);
+    let start = Instant::now();
+    for _ in 0..1_000_000 {
+      *m.lock() += 1;
+    }
+    let single = start.elapsed();
+
+    let start = Instant::now();
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..1_000_000 {
+            *m.lock() += 1;
+          }
+        });
+      }
+    });
+    assert_eq!(*m.lock(), 5_000_000);
+    dbg!(name, single, start.elapsed());
+  }
+
+  #[test]
+  fn raw_mutex_benchmark() {
+    bench::<RawFutexMutex>("futex");
+    bench::<RawSpinLock>("spin");
+    bench::<RawPthreadMutex>("pthread");
+  }
+}
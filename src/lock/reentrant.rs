@@ -0,0 +1,177 @@
+use std::{
+  cell::Cell,
+  marker::PhantomData,
+  ops::Deref,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::lockdep;
+use super::mutex::{RawFutexMutex, current_thread};
+use super::raw::RawMutex;
+
+// A mutex the holding thread may lock again. Since several guards of the
+// same thread can be alive at once, they only hand out shared references;
+// use a `Cell` or `RefCell` inside for mutation.
+pub struct ReentrantMutex<T> {
+  raw: RawFutexMutex,
+  // `current_thread()` of the holder, or 0.
+  owner: AtomicUsize,
+  // How many guards the holder has. Only touched by the holder.
+  count: Cell<u32>,
+  class: lockdep::Class,
+  value: T,
+}
+
+unsafe impl<T> Sync for ReentrantMutex<T> where T: Send {}
+
+impl<T> ReentrantMutex<T> {
+  pub const fn new(value: T) -> Self {
+    Self {
+      raw: RawFutexMutex::INIT,
+      owner: AtomicUsize::new(0),
+      count: Cell::new(0),
+      class: lockdep::Class::new(),
+      value,
+    }
+  }
+
+  pub fn lock(&self) -> ReentrantMutexGuard<'_, T> {
+    let me = current_thread();
+    // Relaxed is enough: only this thread could have stored `me`.
+    if self.owner.load(Ordering::Relaxed) == me {
+      // Can't block, so only the outermost lock counts for lockdep.
+      self.increment();
+    } else {
+      lockdep::acquire(&self.class);
+      self.raw.lock();
+      lockdep::acquired(&self.class);
+      self.owner.store(me, Ordering::Relaxed);
+      self.count.set(1);
+    }
+    ReentrantMutexGuard {
+      mutex: self,
+      _marker: PhantomData,
+    }
+  }
+
+  pub fn try_lock(&self) -> Option<ReentrantMutexGuard<'_, T>> {
+    let me = current_thread();
+    if self.owner.load(Ordering::Relaxed) == me {
+      self.increment();
+    } else if self.raw.try_lock() {
+      lockdep::acquired(&self.class);
+      self.owner.store(me, Ordering::Relaxed);
+      self.count.set(1);
+    } else {
+      return None;
+    }
+    Some(ReentrantMutexGuard {
+      mutex: self,
+      _marker: PhantomData,
+    })
+  }
+
+  fn increment(&self) {
+    let count = self.count.get().checked_add(1);
+    self
+      .count
+      .set(count.expect("lock count overflow in reentrant mutex"));
+  }
+
+  pub fn into_inner(self) -> T {
+    self.value
+  }
+}
+
+pub struct ReentrantMutexGuard<'a, T> {
+  mutex: &'a ReentrantMutex<T>,
+  // Must be dropped on the thread that locked.
+  _marker: PhantomData<*const ()>,
+}
+
+impl<T> Deref for ReentrantMutexGuard<'_, T> {
+  type Target = T;
+  fn deref(&self) -> &Self::Target {
+    &self.mutex.value
+  }
+}
+
+impl<T> Drop for ReentrantMutexGuard<'_, T> {
+  fn drop(&mut self) {
+    let m = self.mutex;
+    let count = m.count.get() - 1;
+    m.count.set(count);
+    if count == 0 {
+      m.owner.store(0, Ordering::Relaxed);
+      lockdep::release(&m.class);
+      unsafe { m.raw.unlock() };
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{cell::RefCell, panic, thread};
+
+  #[test]
+  fn test_nested() {
+    let m = ReentrantMutex::new(RefCell::new(Vec::new()));
+    fn recurse(m: &ReentrantMutex<RefCell<Vec<u32>>>, depth: u32) {
+      let g = m.lock();
+      g.borrow_mut().push(depth);
+      if depth < 100 {
+        recurse(m, depth + 1);
+      }
+      assert_eq!(m.count.get(), depth + 1);
+    }
+    recurse(&m, 0);
+    assert_eq!(m.count.get(), 0);
+    assert_eq!(m.owner.load(Ordering::Relaxed), 0);
+    assert_eq!(m.into_inner().into_inner(), (0..=100).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn test_exclusion() {
+    let m = ReentrantMutex::new(Cell::new(0));
+    let g = m.lock();
+    thread::scope(|s| {
+      s.spawn(|| assert!(m.try_lock().is_none()));
+    });
+    assert!(m.try_lock().is_some());
+    drop(g);
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..10_000 {
+            let outer = m.lock();
+            let inner = m.lock();
+            // Nobody else gets in between.
+            let n = outer.get();
+            inner.set(n + 1);
+          }
+        });
+      }
+    });
+    assert_eq!(m.into_inner().get(), 40_000);
+  }
+
+  #[test]
+  fn test_unwind() {
+    let m = ReentrantMutex::new(Cell::new(0));
+    let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+      let _a = m.lock();
+      let b = m.lock();
+      let _c = m.lock();
+      b.set(1);
+      panic!("in the middle");
+    }));
+    assert!(r.is_err());
+    // Every nested guard was released on the way out.
+    assert_eq!(m.count.get(), 0);
+    thread::scope(|s| {
+      s.spawn(|| m.lock().set(2));
+    });
+    assert_eq!(m.lock().get(), 2);
+  }
+}
@@ -1,18 +1,47 @@
 use std::cell::UnsafeCell;
-use std::io::Write;
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use atomic_wait::{wait, wake_all, wake_one};
 
+use super::backoff::{Backoff, SpinPolicy};
+use super::futex;
+use super::lockdep;
+use super::poison::{Flag, LockResult};
+
 pub struct RwLock<T> {
-  // the numbers of readers, or u32::MAX if write-locked.
+  // The number of readers times READER, plus UPGRADABLE if there's an
+  // upgradable reader, plus WRITER_WAITING if a writer is waiting (or an
+  // upgradable reader waits to upgrade), or WRITE_LOCKED if write-locked.
   state: AtomicU32,
   // Incremented to wake up writers.
   writer_wait_counter: AtomicU32,
+  preference: Preference,
+  // Doesn't spin by default.
+  spin: SpinPolicy,
+  class: lockdep::Class,
   val: UnsafeCell<T>,
 }
 
+const WRITER_WAITING: u32 = 1;
+const UPGRADABLE: u32 = 2;
+const READER: u32 = 4;
+const WRITE_LOCKED: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preference {
+  // New readers get in as long as it isn't write-locked. A steady
+  // stream of readers can starve writers.
+  Readers,
+  // New readers wait once a writer is waiting, so they can't starve it.
+  // A thread must then not take a read lock it already holds again.
+  Writers,
+}
+
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
 pub struct ReadGuard<'a, T> {
   lock: &'a RwLock<T>,
 }
@@ -26,14 +55,113 @@ impl<T> Deref for ReadGuard<'_, T> {
 
 impl<T> Drop for ReadGuard<'_, T> {
   fn drop(&mut self) {
-    if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+    lockdep::release(&self.lock.class);
+    // Wake a writer if we were the last reader it was waiting for. If
+    // there's an upgradable reader, that might be the one waiting (to
+    // upgrade), rather than the writers who can't get in anyway.
+    let rest = self.lock.state.fetch_sub(READER, Ordering::Release) - READER;
+    if rest == WRITER_WAITING {
       self
         .lock
         .writer_wait_counter
         .fetch_add(1, Ordering::Release);
       wake_one(&self.lock.writer_wait_counter);
+    } else if rest == UPGRADABLE + WRITER_WAITING {
+      self
+        .lock
+        .writer_wait_counter
+        .fetch_add(1, Ordering::Release);
+      wake_all(&self.lock.writer_wait_counter);
+    }
+  }
+}
+
+// Shares the lock with plain readers, but not with other upgradable
+// readers, so it can become a writer without letting another in between.
+pub struct UpgradableReadGuard<'a, T> {
+  lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for UpgradableReadGuard<'_, T> {
+  type Target = T;
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.lock.val.get() }
+  }
+}
+
+impl<'a, T> UpgradableReadGuard<'a, T> {
+  // Waits for the other readers to go away. New readers are kept out
+  // meanwhile with `Preference::Writers`.
+  pub fn upgrade(self) -> WriteGuard<'a, T> {
+    let lock = self.lock;
+    std::mem::forget(self);
+    let mut s = lock.state.load(Ordering::Relaxed);
+    loop {
+      if s < READER {
+        match lock
+          .state
+          .compare_exchange(s, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+        {
+          Ok(_) => return WriteGuard { lock },
+          Err(prev) => {
+            s = prev;
+            continue;
+          }
+        }
+      }
+      // Let the last reader know to wake us.
+      if s & WRITER_WAITING == 0
+        && let Err(prev) =
+          lock
+            .state
+            .compare_exchange(s, s + WRITER_WAITING, Ordering::Relaxed, Ordering::Relaxed)
+      {
+        s = prev;
+        continue;
+      }
+      let w = lock.writer_wait_counter.load(Ordering::Acquire);
+      s = lock.state.load(Ordering::Relaxed);
+      // As in `lock_write`: a writer timing out may have cleared the bit,
+      // and then the last reader wouldn't wake us. Set it again.
+      if s >= READER && s & WRITER_WAITING != 0 {
+        wait(&lock.writer_wait_counter, w);
+        s = lock.state.load(Ordering::Relaxed);
+      }
     }
   }
+
+  // Upgrades only if there are no other readers right now.
+  pub fn try_upgrade(self) -> Result<WriteGuard<'a, T>, Self> {
+    let mut s = self.lock.state.load(Ordering::Relaxed);
+    while s < READER {
+      match self
+        .lock
+        .state
+        .compare_exchange(s, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+      {
+        Ok(_) => {
+          let lock = self.lock;
+          std::mem::forget(self);
+          return Ok(WriteGuard { lock });
+        }
+        Err(prev) => s = prev,
+      }
+    }
+    Err(self)
+  }
+}
+
+impl<T> Drop for UpgradableReadGuard<'_, T> {
+  fn drop(&mut self) {
+    lockdep::release(&self.lock.class);
+    self.lock.state.fetch_sub(UPGRADABLE, Ordering::Release);
+    // Both writers and upgradable readers may be waiting for this.
+    self
+      .lock
+      .writer_wait_counter
+      .fetch_add(1, Ordering::Release);
+    wake_all(&self.lock.writer_wait_counter);
+  }
 }
 
 pub struct WriteGuard<'a, T> {
@@ -53,9 +181,25 @@ impl<T> DerefMut for WriteGuard<'_, T> {
   }
 }
 
+impl<'a, T> WriteGuard<'a, T> {
+  // Turns it into a read lock, without letting any writer in between.
+  pub fn downgrade(self) -> ReadGuard<'a, T> {
+    let lock = self.lock;
+    std::mem::forget(self);
+    lock.state.store(READER, Ordering::Release);
+    // Let waiting readers in, and wake a writer so it can mark itself as
+    // waiting again, or nobody would wake it when we're done reading.
+    lock.writer_wait_counter.fetch_add(1, Ordering::Release);
+    wake_all(&lock.state);
+    wake_one(&lock.writer_wait_counter);
+    ReadGuard { lock }
+  }
+}
+
 impl<T> Drop for WriteGuard<'_, T> {
   fn drop(&mut self) {
-    self.lock.state.store(0, Ordering::Relaxed);
+    lockdep::release(&self.lock.class);
+    self.lock.state.store(0, Ordering::Release);
     self
       .lock
       .writer_wait_counter
@@ -70,43 +214,684 @@ impl<T> RwLock<T> {
     Self {
       state: AtomicU32::new(0),
       writer_wait_counter: AtomicU32::new(0),
+      preference: Preference::Readers,
+      spin: SpinPolicy::NONE,
+      class: lockdep::Class::new(),
       val: UnsafeCell::new(val),
     }
   }
 
-  pub fn read(&self) -> ReadGuard<T> {
+  pub const fn preference(mut self, preference: Preference) -> Self {
+    self.preference = preference;
+    self
+  }
+
+  pub const fn spin_policy(mut self, spin: SpinPolicy) -> Self {
+    self.spin = spin;
+    self
+  }
+
+  fn admits_reader(&self, s: u32) -> bool {
+    match self.preference {
+      Preference::Readers => s != WRITE_LOCKED,
+      Preference::Writers => s & WRITER_WAITING == 0,
+    }
+  }
+
+  pub fn read(&self) -> ReadGuard<'_, T> {
+    lockdep::acquire(&self.class);
+    self.lock_read(None);
+    lockdep::acquired(&self.class);
+    ReadGuard { lock: self }
+  }
+
+  pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+    let mut s = self.state.load(Ordering::Relaxed);
+    while self.admits_reader(s) {
+      assert!(s <= u32::MAX - 2 * READER, "Too many readers");
+      match self
+        .state
+        .compare_exchange_weak(s, s + READER, Ordering::Acquire, Ordering::Relaxed)
+      {
+        Ok(_) => {
+          lockdep::acquired(&self.class);
+          return Some(ReadGuard { lock: self });
+        }
+        Err(prev) => s = prev,
+      }
+    }
+    None
+  }
+
+  pub fn try_read_for(&self, timeout: Duration) -> Option<ReadGuard<'_, T>> {
+    match Instant::now().checked_add(timeout) {
+      Some(deadline) => self.try_read_until(deadline),
+      None => Some(self.read()),
+    }
+  }
+
+  pub fn try_read_until(&self, deadline: Instant) -> Option<ReadGuard<'_, T>> {
+    self.lock_read(Some(deadline)).then(|| {
+      lockdep::acquired(&self.class);
+      ReadGuard { lock: self }
+    })
+  }
+
+  // Returns false only if `deadline` passed before it got read-locked.
+  fn lock_read(&self, deadline: Option<Instant>) -> bool {
+    let mut backoff = Backoff::new(&self.spin);
+    let mut waited = false;
     let mut s = self.state.load(Ordering::Relaxed);
     loop {
-      if s < u32::MAX {
-        assert!(s != u32::MAX - 1, "Too many readers");
+      if self.admits_reader(s) {
+        assert!(s <= u32::MAX - 2 * READER, "Too many readers");
         match self
           .state
-          .compare_exchange_weak(s, s + 1, Ordering::Acquire, Ordering::Relaxed)
+          .compare_exchange_weak(s, s + READER, Ordering::Acquire, Ordering::Relaxed)
         {
-          Ok(_) => return ReadGuard { lock: self },
+          Ok(_) => {
+            backoff.finish(!waited);
+            return true;
+          }
           Err(prev) => s = prev,
         }
+      } else {
+        if !backoff.spin() {
+          if !futex::wait_deadline(&self.state, s, deadline) {
+            return false;
+          }
+          waited = true;
+        }
+        s = self.state.load(Ordering::Relaxed);
       }
-      if s == u32::MAX {
-        wait(&self.state, u32::MAX);
+    }
+  }
+
+  pub fn upgradable_read(&self) -> UpgradableReadGuard<'_, T> {
+    lockdep::acquire(&self.class);
+    let mut s = self.state.load(Ordering::Relaxed);
+    loop {
+      if !self.admits_reader(s) {
+        // Write-locked, or a writer is waiting: wait like a reader.
+        wait(&self.state, s);
         s = self.state.load(Ordering::Relaxed);
+      } else if s & UPGRADABLE == 0 {
+        match self.state.compare_exchange_weak(
+          s,
+          s + UPGRADABLE,
+          Ordering::Acquire,
+          Ordering::Relaxed,
+        ) {
+          Ok(_) => {
+            lockdep::acquired(&self.class);
+            return UpgradableReadGuard { lock: self };
+          }
+          Err(prev) => s = prev,
+        }
+      } else {
+        // Another upgradable reader, which wakes us when it's done.
+        let w = self.writer_wait_counter.load(Ordering::Acquire);
+        s = self.state.load(Ordering::Relaxed);
+        if s != WRITE_LOCKED && s & UPGRADABLE != 0 {
+          wait(&self.writer_wait_counter, w);
+          s = self.state.load(Ordering::Relaxed);
+        }
       }
     }
   }
 
-  pub fn write(&self) -> WriteGuard<T> {
-    while let Err(prev) =
-      self
+  pub fn write(&self) -> WriteGuard<'_, T> {
+    lockdep::acquire(&self.class);
+    self.lock_write(None);
+    lockdep::acquired(&self.class);
+    WriteGuard { lock: self }
+  }
+
+  pub fn try_write(&self) -> Option<WriteGuard<'_, T>> {
+    let mut s = self.state.load(Ordering::Relaxed);
+    while s <= WRITER_WAITING {
+      match self
         .state
-        .compare_exchange(0, u32::MAX, Ordering::Acquire, Ordering::Relaxed)
-    {
+        .compare_exchange(s, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+      {
+        Ok(_) => {
+          lockdep::acquired(&self.class);
+          return Some(WriteGuard { lock: self });
+        }
+        Err(prev) => s = prev,
+      }
+    }
+    None
+  }
+
+  pub fn try_write_for(&self, timeout: Duration) -> Option<WriteGuard<'_, T>> {
+    match Instant::now().checked_add(timeout) {
+      Some(deadline) => self.try_write_until(deadline),
+      None => Some(self.write()),
+    }
+  }
+
+  pub fn try_write_until(&self, deadline: Instant) -> Option<WriteGuard<'_, T>> {
+    self.lock_write(Some(deadline)).then(|| {
+      lockdep::acquired(&self.class);
+      WriteGuard { lock: self }
+    })
+  }
+
+  // Returns false only if `deadline` passed before it got write-locked.
+  fn lock_write(&self, deadline: Option<Instant>) -> bool {
+    let mut backoff = Backoff::new(&self.spin);
+    let mut waited = false;
+    let mut s = self.state.load(Ordering::Relaxed);
+    loop {
+      // Try to lock if unlocked.
+      if s <= WRITER_WAITING {
+        match self
+          .state
+          .compare_exchange(s, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+        {
+          Ok(_) => break,
+          Err(prev) => {
+            s = prev;
+            continue;
+          }
+        }
+      }
+      // Let the last reader know to wake us (and with
+      // `Preference::Writers`, block new readers).
+      if s & WRITER_WAITING == 0
+        && let Err(prev) =
+          self
+            .state
+            .compare_exchange(s, s + WRITER_WAITING, Ordering::Relaxed, Ordering::Relaxed)
+      {
+        s = prev;
+        continue;
+      }
+      if backoff.spin() {
+        s = self.state.load(Ordering::Relaxed);
+        continue;
+      }
       let w = self.writer_wait_counter.load(Ordering::Acquire);
-      if self.state.load(Ordering::Relaxed) != 0 {
-        // Wait for the RwLock is till locked, but only if
-        // there have been no wake signals since we checked.
-        wait(&self.writer_wait_counter, w);
+      s = self.state.load(Ordering::Relaxed);
+      // Only sleep while still locked, and while whoever unlocks knows to
+      // wake us: WRITE_LOCKED has every bit set, and readers only wake a
+      // writer if WRITER_WAITING is set. It may have been cleared since,
+      // by another writer that got in first; then set it again.
+      if s > WRITER_WAITING && s & WRITER_WAITING != 0 {
+        // Only if there have been no wake signals since we checked.
+        if !futex::wait_deadline(&self.writer_wait_counter, w, deadline) {
+          self.write_timed_out();
+          return false;
+        }
+        waited = true;
+        s = self.state.load(Ordering::Relaxed);
       }
     }
-    WriteGuard { lock: self }
+    backoff.finish(!waited);
+    true
+  }
+
+  // The WRITER_WAITING bit we left behind might be ours alone, and then
+  // nobody would ever clear it, keeping out readers with
+  // `Preference::Writers`. So clear it, and wake the other waiters to
+  // sort it out again: readers to get in, and a writer to set the bit
+  // again. That also passes on a wake up we may have consumed earlier.
+  fn write_timed_out(&self) {
+    let mut s = self.state.load(Ordering::Relaxed);
+    while s != WRITE_LOCKED && s & WRITER_WAITING != 0 {
+      match self.state.compare_exchange_weak(
+        s,
+        s - WRITER_WAITING,
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+      ) {
+        Ok(_) => break,
+        Err(prev) => s = prev,
+      }
+    }
+    self.writer_wait_counter.fetch_add(1, Ordering::Release);
+    if s & UPGRADABLE != 0 {
+      // The bit might have been an upgrading reader's.
+      wake_all(&self.writer_wait_counter);
+    } else {
+      wake_one(&self.writer_wait_counter);
+    }
+    wake_all(&self.state);
+  }
+}
+
+// Opt-in poisoning on top of `RwLock`. Like std, only a panic while
+// holding the write lock poisons it; readers can't leave torn data behind.
+pub struct PoisonRwLock<T> {
+  inner: RwLock<T>,
+  poison: Flag,
+}
+
+impl<T> PoisonRwLock<T> {
+  pub const fn new(val: T) -> Self {
+    Self {
+      inner: RwLock::new(val),
+      poison: Flag::new(),
+    }
+  }
+
+  pub fn read(&self) -> LockResult<ReadGuard<'_, T>> {
+    self.poison.result(self.inner.read())
+  }
+
+  pub fn write(&self) -> LockResult<PoisonWriteGuard<'_, T>> {
+    let guard = self.inner.write();
+    self.poison.result(PoisonWriteGuard {
+      guard,
+      poison: &self.poison,
+      panicking: thread::panicking(),
+    })
+  }
+
+  pub fn is_poisoned(&self) -> bool {
+    self.poison.get()
+  }
+
+  pub fn clear_poison(&self) {
+    self.poison.clear();
+  }
+}
+
+pub struct PoisonWriteGuard<'a, T> {
+  guard: WriteGuard<'a, T>,
+  poison: &'a Flag,
+  panicking: bool,
+}
+
+impl<T> Deref for PoisonWriteGuard<'_, T> {
+  type Target = T;
+  fn deref(&self) -> &Self::Target {
+    &self.guard
+  }
+}
+
+impl<T> DerefMut for PoisonWriteGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.guard
+  }
+}
+
+impl<T> Drop for PoisonWriteGuard<'_, T> {
+  fn drop(&mut self) {
+    // Runs before `guard` is dropped, i.e. while still write-locked.
+    self.poison.done(self.panicking);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_poison() {
+    let l = PoisonRwLock::new(vec![1]);
+    thread::scope(|s| {
+      // A panicking reader doesn't poison.
+      let r = s.spawn(|| {
+        let _g = l.read().unwrap();
+        panic!("reader");
+      });
+      assert!(r.join().is_err());
+    });
+    assert!(!l.is_poisoned());
+
+    thread::scope(|s| {
+      let r = s.spawn(|| {
+        l.write().unwrap().push(2);
+        let _g = l.write().unwrap();
+        panic!("writer");
+      });
+      assert!(r.join().is_err());
+    });
+    assert!(l.is_poisoned());
+    assert_eq!(*l.read().err().unwrap().into_inner(), [1, 2]);
+    assert!(l.write().is_err());
+
+    l.clear_poison();
+    l.write().unwrap().push(3);
+    assert_eq!(*l.read().unwrap(), [1, 2, 3]);
+  }
+
+  // How long a writer waits while readers keep the lock read-locked,
+  // or None if it didn't get in before the readers stopped.
+  fn writer_latency(preference: Preference) -> Option<std::time::Duration> {
+    use std::sync::atomic::AtomicBool;
+    use std::time::{Duration, Instant};
+
+    let l = RwLock::new(0).preference(preference);
+    let stop = AtomicBool::new(false);
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          while !stop.load(Ordering::Relaxed) {
+            let _g = l.read();
+            thread::sleep(Duration::from_millis(1));
+          }
+        });
+      }
+      thread::sleep(Duration::from_millis(20));
+      let writer = s.spawn(|| {
+        let start = Instant::now();
+        *l.write() += 1;
+        (start.elapsed(), stop.load(Ordering::Relaxed))
+      });
+      thread::sleep(Duration::from_millis(500));
+      stop.store(true, Ordering::Relaxed);
+      let (latency, stopped) = writer.join().unwrap();
+      (!stopped).then_some(latency)
+    })
+  }
+
+  #[test]
+  fn test_writer_starvation() {
+    let latency = writer_latency(Preference::Writers);
+    dbg!(latency);
+    assert!(latency.unwrap() < std::time::Duration::from_millis(100));
+    dbg!(writer_latency(Preference::Readers));
+  }
+
+  #[test]
+  fn test_rwlock() {
+    for preference in [Preference::Readers, Preference::Writers] {
+      let l = RwLock::new(Vec::new()).preference(preference);
+      thread::scope(|s| {
+        for i in 0..4 {
+          let l = &l;
+          s.spawn(move || {
+            for j in 0..1000 {
+              l.write().push(i * 1000 + j);
+              let r1 = l.read();
+              assert!(r1.len() <= 4000);
+            }
+          });
+        }
+      });
+      let mut v = l.read().clone();
+      v.sort();
+      assert_eq!(v, (0..4000).collect::<Vec<_>>());
+      assert_eq!(l.state.load(Ordering::Relaxed), 0);
+    }
+  }
+
+  #[test]
+  fn test_upgradable_read() {
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    let l = RwLock::new(0);
+    let u = l.upgradable_read();
+    // Shared with plain readers ..
+    let r = l.read();
+    assert_eq!(*r + *u, 0);
+    // .. so it can't upgrade while they're around.
+    let u = u.try_upgrade().err().unwrap();
+    drop(r);
+    let mut w = u.try_upgrade().ok().unwrap();
+    *w += 1;
+    drop(w);
+
+    // But not with other upgradable readers.
+    let second = AtomicBool::new(false);
+    thread::scope(|s| {
+      let u = l.upgradable_read();
+      s.spawn(|| {
+        let u = l.upgradable_read();
+        second.store(true, Ordering::Relaxed);
+        *u.upgrade() += 1;
+      });
+      thread::sleep(Duration::from_millis(50));
+      assert!(!second.load(Ordering::Relaxed));
+      *u.upgrade() += 1;
+    });
+    assert_eq!(*l.read(), 3);
+    assert_eq!(l.state.load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn test_upgrade_waits_for_readers() {
+    use std::time::{Duration, Instant};
+
+    for preference in [Preference::Readers, Preference::Writers] {
+      let l = RwLock::new(0).preference(preference);
+      thread::scope(|s| {
+        let r = l.read();
+        let t = s.spawn(|| {
+          let start = Instant::now();
+          *l.upgradable_read().upgrade() += 1;
+          assert!(start.elapsed() >= Duration::from_millis(50));
+        });
+        thread::sleep(Duration::from_millis(50));
+        drop(r);
+        t.join().unwrap();
+      });
+      assert_eq!(*l.read(), 1);
+      assert_eq!(l.state.load(Ordering::Relaxed), 0);
+    }
+  }
+
+  #[test]
+  fn test_downgrade() {
+    use std::time::Duration;
+
+    let l = RwLock::new(0);
+    thread::scope(|s| {
+      let mut w = l.write();
+      let reader = s.spawn(|| *l.read());
+      let writer = s.spawn(|| *l.write() += 1);
+      thread::sleep(Duration::from_millis(50));
+      *w = 1;
+      let r = w.downgrade();
+      // The reader gets in alongside us, the writer doesn't.
+      assert_eq!(reader.join().unwrap(), 1);
+      thread::sleep(Duration::from_millis(50));
+      assert_eq!(*r, 1);
+      drop(r);
+      writer.join().unwrap();
+    });
+    assert_eq!(*l.read(), 2);
+    assert_eq!(l.state.load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn test_upgradable_cache() {
+    use std::collections::HashMap;
+
+    // Look up, and only insert if missing, without two threads both
+    // deciding to insert the same key.
+    let cache = RwLock::new(HashMap::new()).preference(Preference::Writers);
+    thread::scope(|s| {
+      for t in 0..8 {
+        let cache = &cache;
+        s.spawn(move || {
+          for k in 0..1000 {
+            if cache.read().contains_key(&k) {
+              continue;
+            }
+            let u = cache.upgradable_read();
+            if !u.contains_key(&k) {
+              let mut w = u.upgrade();
+              assert!(w.insert(k, t).is_none());
+              let r = w.downgrade();
+              assert!(r.contains_key(&k));
+            }
+          }
+        });
+      }
+    });
+    assert_eq!(cache.read().len(), 1000);
+    assert_eq!(cache.state.load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn test_try_lock() {
+    let l = RwLock::new(0);
+    let r = l.read();
+    assert!(l.try_read().is_some());
+    assert!(l.try_write().is_none());
+    assert!(l.try_write_for(Duration::from_millis(10)).is_none());
+    drop(r);
+    let w = l.try_write().unwrap();
+    assert!(l.try_read().is_none());
+    assert!(l.try_read_for(Duration::from_millis(10)).is_none());
+    assert!(l.try_write_until(Instant::now()).is_none());
+    drop(w);
+    assert!(l.try_read_for(Duration::from_millis(10)).is_some());
+    assert_eq!(l.state.load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn test_timed_acquires_after_release() {
+    let l = RwLock::new(0);
+    thread::scope(|s| {
+      let w = l.write();
+      let reader = s.spawn(|| *l.try_read_for(Duration::from_secs(10)).unwrap());
+      thread::sleep(Duration::from_millis(50));
+      drop(w);
+      assert_eq!(reader.join().unwrap(), 0);
+
+      let r = l.read();
+      let writer = s.spawn(|| *l.try_write_for(Duration::from_secs(10)).unwrap() += 1);
+      thread::sleep(Duration::from_millis(50));
+      drop(r);
+      writer.join().unwrap();
+    });
+    assert_eq!(*l.read(), 1);
+  }
+
+  #[test]
+  fn test_write_timeout_no_lost_wakeup() {
+    for preference in [Preference::Readers, Preference::Writers] {
+      let l = RwLock::new(0).preference(preference);
+      let r = l.read();
+      thread::scope(|s| {
+        // This writer gives up, after having set WRITER_WAITING.
+        s.spawn(|| {
+          let start = Instant::now();
+          assert!(l.try_write_for(Duration::from_millis(50)).is_none());
+          assert!(start.elapsed() >= Duration::from_millis(50));
+        })
+        .join()
+        .unwrap();
+        // Which must not keep readers out ..
+        drop(l.try_read().unwrap());
+
+        // .. nor keep another writer from being woken.
+        let writer = s.spawn(|| *l.write() += 1);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!writer.is_finished());
+        drop(r);
+        writer.join().unwrap();
+      });
+      assert_eq!(*l.read(), 1);
+      assert_eq!(l.state.load(Ordering::Relaxed), 0);
+    }
+  }
+
+  #[test]
+  fn test_write_timeout_with_two_writers() {
+    // Two waiting writers, one of which times out: the other one must
+    // still be woken when the readers are done.
+    let l = RwLock::new(0).preference(Preference::Writers);
+    thread::scope(|s| {
+      let r = l.read();
+      let patient = s.spawn(|| *l.write() += 1);
+      let impatient = s.spawn(|| l.try_write_for(Duration::from_millis(50)).is_none());
+      assert!(impatient.join().unwrap());
+      thread::sleep(Duration::from_millis(20));
+      drop(r);
+      patient.join().unwrap();
+    });
+    assert_eq!(*l.read(), 1);
+    assert_eq!(l.state.load(Ordering::Relaxed), 0);
+  }
+
+  #[test]
+  fn test_write_timeout_while_upgrading() {
+    for preference in [Preference::Readers, Preference::Writers] {
+      let l = RwLock::new(0).preference(preference);
+      thread::scope(|s| {
+        let r = l.read();
+        let upgrader = s.spawn(|| *l.upgradable_read().upgrade() += 1);
+        // Writers giving up clear WRITER_WAITING, which may be the
+        // upgrader's.
+        let impatient: Vec<_> = (0..4)
+          .map(|_| {
+            s.spawn(|| {
+              for _ in 0..20 {
+                assert!(l.try_write_for(Duration::from_millis(1)).is_none());
+              }
+            })
+          })
+          .collect();
+        for t in impatient {
+          t.join().unwrap();
+        }
+        assert!(!upgrader.is_finished());
+        drop(r);
+        upgrader.join().unwrap();
+      });
+      assert_eq!(*l.read(), 1);
+      assert_eq!(l.state.load(Ordering::Relaxed), 0);
+    }
+  }
+
+  #[test]
+  fn test_writers_and_readers_stress() {
+    for preference in [Preference::Readers, Preference::Writers] {
+      let l = RwLock::new((0, 0)).preference(preference);
+      thread::scope(|s| {
+        for _ in 0..3 {
+          s.spawn(|| {
+            for _ in 0..5_000 {
+              let mut w = l.write();
+              w.0 += 1;
+              // Let the others pile up behind us.
+              thread::yield_now();
+              w.1 += 1;
+            }
+          });
+        }
+        for _ in 0..3 {
+          s.spawn(|| {
+            for _ in 0..5_000 {
+              let r = l.read();
+              thread::yield_now();
+              assert_eq!(r.0, r.1);
+            }
+          });
+        }
+      });
+      assert_eq!(*l.read(), (15_000, 15_000));
+      assert_eq!(l.state.load(Ordering::Relaxed), 0);
+    }
+  }
+
+  // Same workload as for `primitive::rwlock::RwLock` in its tests.
+  #[test]
+  fn rwlock_benchmark() {
+    let l = RwLock::new(0u64);
+    let start = std::time::Instant::now();
+    thread::scope(|s| {
+      for i in 0..4 {
+        let l = &l;
+        s.spawn(move || {
+          for j in 0..1_000_000 {
+            if (i + j) % 100 == 0 {
+              *l.write() += 1;
+            } else {
+              std::hint::black_box(*l.read());
+            }
+          }
+        });
+      }
+    });
+    assert_eq!(*l.read(), 40_000);
+    dbg!(start.elapsed());
   }
 }
@@ -0,0 +1,182 @@
+use std::{
+  cell::UnsafeCell,
+  mem::MaybeUninit,
+  ptr,
+  sync::atomic::{AtomicU32, Ordering, fence},
+};
+
+use super::backoff::{Backoff, SpinPolicy};
+use super::lockdep;
+use super::mutex::Mutex;
+
+// For small, frequently read and rarely written data. Readers don't write
+// to shared memory at all: they copy the value optimistically and retry if
+// a writer was busy in the meantime, as told by the sequence number.
+pub struct SeqLock<T: Copy> {
+  // Odd while a write is in progress.
+  seq: AtomicU32,
+  // Serializes writers.
+  writer: Mutex<()>,
+  val: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+  pub const fn new(val: T) -> Self {
+    Self {
+      seq: AtomicU32::new(0),
+      writer: Mutex::new(()),
+      val: UnsafeCell::new(val),
+    }
+  }
+
+  pub fn read(&self) -> T {
+    // Waits for the writer, so it must not be held in the opposite order,
+    // though nothing is held afterwards. `try_read` doesn't wait.
+    lockdep::acquire(self.writer.class());
+    let policy = SpinPolicy::exponential();
+    let mut backoff = Backoff::new(&policy);
+    loop {
+      if let Some(val) = self.try_read() {
+        return val;
+      }
+      backoff.snooze();
+    }
+  }
+
+  // A single attempt, which fails if a write was in progress.
+  pub fn try_read(&self) -> Option<T> {
+    let s1 = self.seq.load(Ordering::Acquire);
+    if s1 & 1 == 1 {
+      return None;
+    }
+    // This may race with a writer and see a torn value, so it is read as
+    // MaybeUninit, and only trusted once the sequence number confirms no
+    // writer was involved. (Strictly a data race in the Rust memory
+    // model, like in every seqlock, hence the volatile read.)
+    let val = unsafe { ptr::read_volatile(self.val.get() as *const MaybeUninit<T>) };
+    // Keeps the read of `val` before the second load of `seq`.
+    fence(Ordering::Acquire);
+    let s2 = self.seq.load(Ordering::Relaxed);
+    (s1 == s2).then(|| unsafe { val.assume_init() })
+  }
+
+  pub fn write(&self, val: T) {
+    self.update(|v| *v = val);
+  }
+
+  pub fn update(&self, f: impl FnOnce(&mut T)) {
+    let _writer = self.writer.lock();
+    // On a copy, before `seq` goes odd: if `f` panics, readers never
+    // notice, rather than spinning forever on an odd `seq`.
+    let mut val = unsafe { *self.val.get() };
+    f(&mut val);
+    let s = self.seq.load(Ordering::Relaxed);
+    self.seq.store(s.wrapping_add(1), Ordering::Relaxed);
+    // Keeps the odd `seq` store before the writes to `val`.
+    fence(Ordering::Release);
+    unsafe { ptr::write_volatile(self.val.get(), val) };
+    self.seq.store(s.wrapping_add(2), Ordering::Release);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{sync::atomic::AtomicBool, thread};
+
+  #[derive(Clone, Copy)]
+  struct Config {
+    id: u64,
+    limits: [u64; 7],
+    checksum: u64,
+  }
+
+  impl Config {
+    fn new(id: u64) -> Self {
+      let limits = [id; 7];
+      Config {
+        id,
+        limits,
+        checksum: limits.iter().sum::<u64>() ^ id,
+      }
+    }
+
+    fn is_consistent(&self) -> bool {
+      self.limits.iter().all(|&l| l == self.id)
+        && self.checksum == self.limits.iter().sum::<u64>() ^ self.id
+    }
+  }
+
+  #[test]
+  fn test_seqlock() {
+    let l = SeqLock::new(Config::new(0));
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+      let writer = s.spawn(|| {
+        for id in 1..=200_000 {
+          l.write(Config::new(id));
+        }
+        done.store(true, Ordering::Relaxed);
+      });
+      for _ in 0..4 {
+        s.spawn(|| {
+          let mut last = 0;
+          let mut reads = 0;
+          while !done.load(Ordering::Relaxed) {
+            let c = l.read();
+            assert!(c.is_consistent(), "torn read of config {}", c.id);
+            assert!(c.id >= last);
+            last = c.id;
+            reads += 1;
+          }
+          dbg!(reads);
+        });
+      }
+      writer.join().unwrap();
+    });
+    assert_eq!(l.read().id, 200_000);
+  }
+
+  #[test]
+  fn test_concurrent_writers() {
+    let l = SeqLock::new((0u64, 0u64));
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..10_000 {
+            l.update(|(a, b)| {
+              *a += 1;
+              *b += 2;
+            });
+          }
+        });
+      }
+      s.spawn(|| {
+        for _ in 0..10_000 {
+          let (a, b) = l.read();
+          assert_eq!(a * 2, b);
+        }
+      });
+    });
+    assert_eq!(l.read(), (40_000, 80_000));
+    assert!(l.try_read().is_some());
+  }
+
+  #[test]
+  fn test_update_panics() {
+    let l = SeqLock::new(1);
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+      l.update(|v| {
+        *v = 2;
+        panic!("oops");
+      })
+    }));
+    assert!(r.is_err());
+    // Neither half-written nor stuck.
+    assert_eq!(l.try_read(), Some(1));
+    l.write(3);
+    assert_eq!(l.read(), 3);
+  }
+}
@@ -0,0 +1,211 @@
+use std::{
+  cell::UnsafeCell,
+  ops::{Deref, DerefMut},
+  sync::atomic::{AtomicU32, Ordering},
+};
+
+use atomic_wait::{wait, wake_all, wake_one};
+
+use super::lockdep;
+use super::mutex::{Mutex, MutexGuard};
+
+// A reader count on its own cache line, so readers on different CPUs
+// don't bounce a shared line like they do on `RwLock::state`.
+#[repr(align(64))]
+struct Slot(AtomicU32);
+
+// A read-mostly RwLock with a reader count per CPU. Readers only touch the
+// slot of the CPU they run on, while a writer announces itself through
+// `writing` and then waits for every slot to drain. Writers are
+// preferred: new readers back off as soon as one shows up.
+pub struct ShardedRwLock<T> {
+  slots: Box<[Slot]>,
+  // 1 while a writer holds the lock or waits for readers to leave.
+  writing: AtomicU32,
+  // Serializes writers.
+  writer: Mutex<()>,
+  class: lockdep::Class,
+  val: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for ShardedRwLock<T> where T: Send + Sync {}
+
+fn current_cpu() -> usize {
+  let cpu = unsafe { libc::sched_getcpu() };
+  if cpu < 0 { 0 } else { cpu as usize }
+}
+
+pub struct ReadGuard<'a, T> {
+  lock: &'a ShardedRwLock<T>,
+  // The thread may have moved to another CPU by the time it unlocks.
+  slot: usize,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+  type Target = T;
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.lock.val.get() }
+  }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+  fn drop(&mut self) {
+    lockdep::release(&self.lock.class);
+    self.lock.read_unlock(self.slot);
+  }
+}
+
+pub struct WriteGuard<'a, T> {
+  lock: &'a ShardedRwLock<T>,
+  _writer: MutexGuard<'a, ()>,
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+  type Target = T;
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.lock.val.get() }
+  }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    unsafe { &mut *self.lock.val.get() }
+  }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+  fn drop(&mut self) {
+    lockdep::release(&self.lock.class);
+    // `_writer` is only dropped after this, letting in the next writer.
+    self.lock.writing.store(0, Ordering::Release);
+    wake_all(&self.lock.writing);
+  }
+}
+
+impl<T> ShardedRwLock<T> {
+  pub fn new(val: T) -> Self {
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }.max(1) as usize;
+    Self {
+      slots: (0..cpus).map(|_| Slot(AtomicU32::new(0))).collect(),
+      writing: AtomicU32::new(0),
+      writer: Mutex::new(()),
+      class: lockdep::Class::new(),
+      val: UnsafeCell::new(val),
+    }
+  }
+
+  pub fn read(&self) -> ReadGuard<'_, T> {
+    lockdep::acquire(&self.class);
+    let slot = current_cpu() % self.slots.len();
+    loop {
+      // SeqCst on both sides: either we see `writing`, or the writer
+      // sees our slot count when it scans the slots.
+      self.slots[slot].0.fetch_add(1, Ordering::SeqCst);
+      if self.writing.load(Ordering::SeqCst) == 0 {
+        lockdep::acquired(&self.class);
+        return ReadGuard { lock: self, slot };
+      }
+      // A writer got in first. Back off, and wait for it to finish.
+      self.read_unlock(slot);
+      wait(&self.writing, 1);
+    }
+  }
+
+  fn read_unlock(&self, slot: usize) {
+    let count = &self.slots[slot].0;
+    if count.fetch_sub(1, Ordering::SeqCst) == 1 && self.writing.load(Ordering::SeqCst) != 0 {
+      wake_one(count);
+    }
+  }
+
+  pub fn write(&self) -> WriteGuard<'_, T> {
+    lockdep::acquire(&self.class);
+    let writer = self.writer.lock();
+    self.writing.store(1, Ordering::SeqCst);
+    for slot in self.slots.iter() {
+      loop {
+        let n = slot.0.load(Ordering::SeqCst);
+        if n == 0 {
+          break;
+        }
+        wait(&slot.0, n);
+      }
+    }
+    lockdep::acquired(&self.class);
+    WriteGuard {
+      lock: self,
+      _writer: writer,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lock::rwlock::RwLock;
+  use std::{thread, time::Instant};
+
+  #[test]
+  fn test_sharded_rwlock() {
+    let l = ShardedRwLock::new((0, 0));
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..10_000 {
+            let mut w = l.write();
+            w.0 += 1;
+            w.1 += 1;
+          }
+        });
+      }
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..100_000 {
+            let r = l.read();
+            // Never half-way through a write.
+            assert_eq!(r.0, r.1);
+          }
+        });
+      }
+    });
+    assert_eq!(*l.read(), (40_000, 40_000));
+    assert!(l.slots.iter().all(|s| s.0.load(Ordering::Relaxed) == 0));
+  }
+
+  fn read_bench(readers: usize) {
+    const READS: usize = 100_000;
+
+    let l = RwLock::new(1u64);
+    let start = Instant::now();
+    thread::scope(|s| {
+      for _ in 0..readers {
+        s.spawn(|| {
+          for _ in 0..READS {
+            std::hint::black_box(*l.read());
+          }
+        });
+      }
+    });
+    let rwlock = start.elapsed();
+
+    let l = ShardedRwLock::new(1u64);
+    let start = Instant::now();
+    thread::scope(|s| {
+      for _ in 0..readers {
+        s.spawn(|| {
+          for _ in 0..READS {
+            std::hint::black_box(*l.read());
+          }
+        });
+      }
+    });
+    dbg!(readers, rwlock, start.elapsed());
+  }
+
+  #[test]
+  fn sharded_rwlock_benchmark() {
+    read_bench(1);
+    read_bench(8);
+    read_bench(64);
+  }
+}
@@ -1,20 +1,62 @@
-use std::sync::atomic::{AtomicBool, Ordering::Acquire, Ordering::Release};
+use std::sync::atomic::{AtomicBool, Ordering::Acquire, Ordering::Relaxed, Ordering::Release};
 
-pub struct SpinLock {
+use super::backoff::{Backoff, SpinPolicy};
+use super::raw::{self, GuardSend, RawMutex};
+
+// How a waiting thread tries to take the lock.
+pub enum Strategy {
+  // Swap until it succeeds. Every attempt is a write, which keeps pulling
+  // the cache line away from the holder.
+  TestAndSet,
+  // Spin on a plain load until the lock looks free, and only then swap.
+  TestAndTestAndSet,
+}
+
+// A bare spin lock, for building other primitives on.
+pub struct RawSpinLock {
   lock: AtomicBool,
+  spin: SpinPolicy,
+  strategy: Strategy,
 }
 
-impl SpinLock {
+impl RawSpinLock {
   pub const fn new() -> Self {
-    SpinLock {
+    RawSpinLock {
       lock: AtomicBool::new(false),
+      spin: SpinPolicy::FOREVER,
+      strategy: Strategy::TestAndTestAndSet,
     }
   }
 
+  pub const fn spin_policy(mut self, spin: SpinPolicy) -> Self {
+    self.spin = spin;
+    self
+  }
+
+  pub const fn strategy(mut self, strategy: Strategy) -> Self {
+    self.strategy = strategy;
+    self
+  }
+
   pub fn lock(&self) {
-    while self.lock.swap(true, Acquire) {
-      std::hint::spin_loop();
+    let mut backoff = Backoff::new(&self.spin);
+    match self.strategy {
+      Strategy::TestAndSet => {
+        while self.lock.swap(true, Acquire) {
+          backoff.snooze();
+        }
+      }
+      Strategy::TestAndTestAndSet => loop {
+        // Every swap, the first one too, waits until the lock looks free.
+        while self.lock.load(Relaxed) {
+          backoff.snooze();
+        }
+        if !self.lock.swap(true, Acquire) {
+          break;
+        }
+      },
     }
+    backoff.finish(true);
 
     // while self
     //   .lock
@@ -25,7 +67,143 @@ impl SpinLock {
     // }
   }
 
-  pub fn unlock(&self) {
+  pub fn try_lock(&self) -> bool {
+    !self.lock.load(Relaxed) && !self.lock.swap(true, Acquire)
+  }
+
+  pub fn is_locked(&self) -> bool {
+    self.lock.load(Relaxed)
+  }
+
+  // Safety: only when locked by the current context.
+  pub unsafe fn unlock(&self) {
     self.lock.store(false, Release);
   }
 }
+
+unsafe impl RawMutex for RawSpinLock {
+  const INIT: Self = RawSpinLock::new();
+
+  type GuardMarker = GuardSend;
+
+  fn lock(&self) {
+    RawSpinLock::lock(self);
+  }
+
+  fn try_lock(&self) -> bool {
+    RawSpinLock::try_lock(self)
+  }
+
+  unsafe fn unlock(&self) {
+    unsafe { RawSpinLock::unlock(self) };
+  }
+}
+
+pub struct SpinLock<T> {
+  inner: raw::Mutex<RawSpinLock, T>,
+}
+
+pub type Guard<'a, T> = raw::MutexGuard<'a, RawSpinLock, T>;
+
+impl<T> SpinLock<T> {
+  pub const fn new(value: T) -> Self {
+    SpinLock {
+      inner: raw::Mutex::new(value),
+    }
+  }
+
+  pub const fn spin_policy(mut self, spin: SpinPolicy) -> Self {
+    self.inner.raw = self.inner.raw.spin_policy(spin);
+    self
+  }
+
+  pub const fn strategy(mut self, strategy: Strategy) -> Self {
+    self.inner.raw = self.inner.raw.strategy(strategy);
+    self
+  }
+
+  pub fn lock(&self) -> Guard<'_, T> {
+    self.inner.lock()
+  }
+
+  pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+    self.inner.try_lock()
+  }
+
+  // Only a snapshot, which may be stale by the time it's looked at.
+  pub fn is_locked(&self) -> bool {
+    self.inner.raw.is_locked()
+  }
+
+  // For when the lock was taken on the other side of an FFI boundary and
+  // its guard forgotten.
+  //
+  // Safety: the lock must be held, and nothing may use the value through
+  // the guard that took it anymore.
+  pub unsafe fn force_unlock(&self) {
+    unsafe { self.inner.force_unlock() };
+  }
+
+  pub fn into_inner(self) -> T {
+    self.inner.into_inner()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{thread, time::Instant};
+
+  #[test]
+  fn test_spin_lock() {
+    let l = SpinLock::new(0);
+    let g = l.lock();
+    assert!(l.is_locked());
+    assert!(l.try_lock().is_none());
+    drop(g);
+    assert!(!l.is_locked());
+    *l.try_lock().unwrap() += 1;
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..10_000 {
+            *l.lock() += 1;
+          }
+        });
+      }
+    });
+    assert_eq!(l.into_inner(), 40_001);
+  }
+
+  #[test]
+  fn test_force_unlock() {
+    let l = SpinLock::new(0);
+    std::mem::forget(l.lock());
+    assert!(l.is_locked());
+    unsafe { l.force_unlock() };
+    *l.lock() += 1;
+    assert!(!l.is_locked());
+  }
+
+  #[test]
+  fn strategy_benchmark() {
+    for (name, strategy) in [
+      ("tas", Strategy::TestAndSet),
+      ("ttas", Strategy::TestAndTestAndSet),
+    ] {
+      let l = SpinLock::new(0).strategy(strategy);
+      let start = Instant::now();
+      thread::scope(|s| {
+        for _ in 0..4 {
+          s.spawn(|| {
+            for _ in 0..1_000_000 {
+              *l.lock() += 1;
+            }
+          });
+        }
+      });
+      assert_eq!(l.into_inner(), 4_000_000);
+      dbg!(name, start.elapsed());
+    }
+  }
+}
@@ -0,0 +1,97 @@
+use std::sync::atomic::{
+  AtomicU32,
+  Ordering::{Acquire, Relaxed, Release},
+};
+
+use super::backoff::{Backoff, SpinPolicy};
+use super::raw::{self, GuardSend, RawMutex};
+
+// A FIFO spin lock: every locker draws a ticket, and waits until it is
+// served. All waiters still spin on the same `serving` cache line.
+pub struct RawTicketLock {
+  next: AtomicU32,
+  serving: AtomicU32,
+  spin: SpinPolicy,
+}
+
+pub type TicketLock<T> = raw::Mutex<RawTicketLock, T>;
+
+pub type Guard<'a, T> = raw::MutexGuard<'a, RawTicketLock, T>;
+
+impl RawTicketLock {
+  pub const fn new() -> Self {
+    RawTicketLock {
+      next: AtomicU32::new(0),
+      serving: AtomicU32::new(0),
+      // The next in line can't be overtaken, so spinning while it is
+      // preempted only wastes time. Yield eventually.
+      spin: SpinPolicy::exponential(),
+    }
+  }
+
+  pub const fn spin_policy(mut self, spin: SpinPolicy) -> Self {
+    self.spin = spin;
+    self
+  }
+}
+
+impl Default for RawTicketLock {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+unsafe impl RawMutex for RawTicketLock {
+  const INIT: Self = RawTicketLock::new();
+
+  type GuardMarker = GuardSend;
+
+  fn lock(&self) {
+    let ticket = self.next.fetch_add(1, Relaxed);
+    let mut backoff = Backoff::new(&self.spin);
+    while self.serving.load(Acquire) != ticket {
+      backoff.snooze();
+    }
+    backoff.finish(true);
+  }
+
+  // Only draws a ticket if it would be served right away.
+  fn try_lock(&self) -> bool {
+    let s = self.serving.load(Acquire);
+    self
+      .next
+      .compare_exchange(s, s.wrapping_add(1), Relaxed, Relaxed)
+      .is_ok()
+  }
+
+  unsafe fn unlock(&self) {
+    // Only the holder ever changes `serving`.
+    let s = self.serving.load(Relaxed);
+    self.serving.store(s.wrapping_add(1), Release);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+
+  #[test]
+  fn test_ticket_lock() {
+    let l = TicketLock::new(0);
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..10_000 {
+            *l.lock() += 1;
+          }
+        });
+      }
+    });
+    let g = l.try_lock().unwrap();
+    assert_eq!(*g, 40_000);
+    assert!(l.try_lock().is_none());
+    drop(g);
+    assert_eq!(l.raw.next.load(Relaxed), l.raw.serving.load(Relaxed));
+  }
+}
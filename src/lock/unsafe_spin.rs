@@ -1,66 +1,30 @@
-use std::{
-  cell::UnsafeCell,
-  ops::{Deref, DerefMut},
-  sync::atomic::{
-    AtomicBool,
-    Ordering::{Relaxed, Release},
-  },
-};
+use super::backoff::SpinPolicy;
+use super::raw;
+use super::spin::{RawSpinLock, Strategy};
 
 pub struct UnsafeSpinLock<T> {
-  locked: AtomicBool,
-  value: UnsafeCell<T>,
+  inner: raw::Mutex<RawSpinLock, T>,
 }
 
-unsafe impl<T> Sync for UnsafeSpinLock<T> where T: Send {}
-
-pub struct Guard<'a, T> {
-  lock: &'a UnsafeSpinLock<T>,
-}
+pub type Guard<'a, T> = raw::MutexGuard<'a, RawSpinLock, T>;
 
 impl<T> UnsafeSpinLock<T> {
   pub const fn new(value: T) -> Self {
     UnsafeSpinLock {
-      locked: AtomicBool::new(false),
-      value: UnsafeCell::new(value),
+      inner: raw::Mutex::from_raw(RawSpinLock::new().strategy(Strategy::TestAndSet), value),
     }
   }
 
-  pub fn lock(&self) -> Guard<T> {
-    while self
-      .locked
-      .compare_exchange_weak(false, true, Release, Relaxed)
-      .is_err()
-    {
-      std::hint::spin_loop();
-    }
-    Guard { lock: self }
-  }
-
-  pub fn unlock(&self) {
-    self.locked.store(false, Release);
-  }
-}
-
-unsafe impl<T: Send> Send for Guard<'_, T> {}
-unsafe impl<T: Sync> Sync for Guard<'_, T> {}
-
-impl<T> Deref for Guard<'_, T> {
-  type Target = T;
-
-  fn deref(&self) -> &Self::Target {
-    unsafe { &*self.lock.value.get() }
+  pub const fn spin_policy(mut self, spin: SpinPolicy) -> Self {
+    self.inner.raw = self.inner.raw.spin_policy(spin);
+    self
   }
-}
 
-impl<T> DerefMut for Guard<'_, T> {
-  fn deref_mut(&mut self) -> &mut Self::Target {
-    unsafe { &mut *self.lock.value.get() }
+  pub fn lock(&self) -> Guard<'_, T> {
+    self.inner.lock()
   }
-}
 
-impl<'a, T> Drop for Guard<'a, T> {
-  fn drop(&mut self) {
-    self.lock.unlock();
+  pub fn unlock(&self) {
+    unsafe { self.inner.force_unlock() };
   }
 }
@@ -1,6 +1,5 @@
-#![allow(warnings)]
-use std::thread;
-use std::time::Duration;
+// The modules are examples, exercised by their tests rather than `main`.
+#![allow(dead_code)]
 mod arc;
 mod atomics;
 mod basic;
@@ -10,7 +9,4 @@ mod mem_order;
 mod pin;
 mod primitive;
 
-use crate::lock::condvar::*;
-use crate::lock::mutex::*;
-
 fn main() {}
@@ -9,8 +9,6 @@ use std::{
   time::Duration,
 };
 
-use rand::random;
-
 static X: AtomicI32 = AtomicI32::new(0);
 static Y: AtomicI32 = AtomicI32::new(0);
 
@@ -29,12 +27,13 @@ static LOCK: AtomicBool = AtomicBool::new(false);
 
 pub fn lock() {
   if LOCK.compare_exchange(false, true, Acquire, Relaxed).is_ok() {
-    unsafe { (*(&raw mut DATA)).push('!') };
+    let data = &raw mut DATA;
+    unsafe { (*data).push('!') };
     LOCK.store(false, Relaxed); // Release the lock
   }
 }
 #[derive(Debug, Default)]
-struct Data();
+pub struct Data();
 pub fn get_data() -> &'static Data {
   static PTR: AtomicPtr<Data> = AtomicPtr::new(std::ptr::null_mut());
 
@@ -80,8 +79,7 @@ pub fn seq_cst() {
 
 static mut DATA_ARR: [u64; 10] = [0; 10];
 
-const ATOMIC_FALSE: AtomicBool = AtomicBool::new(false);
-static READY: [AtomicBool; 10] = [ATOMIC_FALSE; 10];
+static READY: [AtomicBool; 10] = [const { AtomicBool::new(false) }; 10];
 
 pub fn order_fence() {
   for i in 0..10 {
@@ -1,4 +1,4 @@
-use std::{env::consts, marker::PhantomPinned, ops::Add, pin::Pin};
+use std::{marker::PhantomPinned, pin::Pin};
 
 struct AddrTracker(Option<usize>);
 
@@ -64,12 +64,12 @@ impl SelfRef {
   }
 }
 
-fn r#move(mut self_ref: SelfRef) {
+fn r#move(self_ref: SelfRef) {
   println!("Moving SelfRef...");
   dbg!(self_ref.name.as_ptr(), self_ref.ptr);
 }
 
-fn move_pin(mut pinned: PinSelf) {
+fn move_pin(pinned: PinSelf) {
   println!("Moving Pinned PinSelf...");
   dbg!(
     pinned.name.as_ref().get_ref() as *const Unmovable,
@@ -90,7 +90,7 @@ mod tests {
 
   #[test]
   fn test_self_ref() {
-    let mut self_ref = SelfRef::new("test".into());
+    let self_ref = SelfRef::new("test".into());
 
     dbg!(self_ref.ptr);
     // [src\pin.rs:61:5] self_ref.ptr = 0x0000004bc3afec50
@@ -102,7 +102,7 @@ mod tests {
 
   #[test]
   fn test_pin() {
-    let mut pinned = PinSelf::new("pinned".into());
+    let pinned = PinSelf::new("pinned".into());
     dbg!(
       pinned.name.as_ref().get_ref() as *const Unmovable,
       pinned.ptr
@@ -112,7 +112,7 @@ mod tests {
 
   #[test]
   fn test_movable() {
-    let mut pinned = Box::pin(Unmovable {
+    let pinned = Box::pin(Unmovable {
       val: "Pinned!".into(),
       _marker: PhantomPinned,
     });
@@ -121,7 +121,7 @@ mod tests {
     //   _marker: PhantomPinned,
     // };
 
-    let pinned_ref = pinned.as_ref().get_ref();
+    let _pinned_ref = pinned.as_ref().get_ref();
     // the trait `Unpin` is not implemented for `std::marker::PhantomPinned`
     // let pinned_mut = pinned.as_mut().get_mut();
   }
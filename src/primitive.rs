@@ -1 +1,3 @@
-mod mutex;
+pub(crate) mod condvar;
+pub(crate) mod mutex;
+mod rwlock;
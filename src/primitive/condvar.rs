@@ -0,0 +1,199 @@
+use std::{
+  cell::UnsafeCell,
+  mem::MaybeUninit,
+  ptr,
+  sync::atomic::{AtomicPtr, Ordering},
+  time::Duration,
+};
+
+use super::mutex::{MutexGuard, cvt};
+use crate::lock::condvar::WaitTimeoutResult;
+
+// The OS counterpart of `lock::condvar::Condvar`, for `primitive::mutex`.
+// Timed waits use CLOCK_MONOTONIC, so they don't jump with the wall clock.
+pub struct Condvar {
+  c: Box<UnsafeCell<libc::pthread_cond_t>>,
+  // The mutex of the first wait. Waiting with two mutexes at once is
+  // undefined in POSIX, so like std, this binds to the first one for good.
+  mutex: AtomicPtr<libc::pthread_mutex_t>,
+}
+
+unsafe impl Send for Condvar {}
+unsafe impl Sync for Condvar {}
+
+impl Condvar {
+  pub fn new() -> Self {
+    let c = Box::new(UnsafeCell::new(libc::PTHREAD_COND_INITIALIZER));
+    unsafe {
+      let mut attr = MaybeUninit::<libc::pthread_condattr_t>::uninit();
+      cvt(libc::pthread_condattr_init(attr.as_mut_ptr())).expect("pthread_condattr_init");
+      let r = cvt(libc::pthread_condattr_setclock(
+        attr.as_mut_ptr(),
+        libc::CLOCK_MONOTONIC,
+      ))
+      .and_then(|_| cvt(libc::pthread_cond_init(c.get(), attr.as_ptr())));
+      libc::pthread_condattr_destroy(attr.as_mut_ptr());
+      r.expect("pthread_cond_init");
+    }
+    Self {
+      c,
+      mutex: AtomicPtr::new(ptr::null_mut()),
+    }
+  }
+
+  fn verify(&self, guard: &MutexGuard<'_>) {
+    let m = guard.mutex.m.get();
+    if let Err(prev) =
+      self
+        .mutex
+        .compare_exchange(ptr::null_mut(), m, Ordering::Relaxed, Ordering::Relaxed)
+    {
+      assert_eq!(
+        prev, m,
+        "attempted to use a condition variable with two mutexes"
+      );
+    }
+  }
+
+  // The guard must not be of a `Kind::Recursive` mutex locked more than once.
+  // Panics if the condvar was used with another mutex before.
+  pub fn wait<'a>(&self, guard: MutexGuard<'a>) -> MutexGuard<'a> {
+    self.verify(&guard);
+    let r = cvt(unsafe { libc::pthread_cond_wait(self.c.get(), guard.mutex.m.get()) });
+    debug_assert_eq!(r, Ok(()));
+    guard
+  }
+
+  pub fn wait_timeout<'a>(
+    &self,
+    guard: MutexGuard<'a>,
+    dur: Duration,
+  ) -> (MutexGuard<'a>, WaitTimeoutResult) {
+    self.verify(&guard);
+    let deadline = monotonic_deadline(dur);
+    let r = unsafe { libc::pthread_cond_timedwait(self.c.get(), guard.mutex.m.get(), &deadline) };
+    debug_assert!(r == 0 || r == libc::ETIMEDOUT);
+    (guard, WaitTimeoutResult::new(r == libc::ETIMEDOUT))
+  }
+
+  pub fn notify_one(&self) {
+    let r = cvt(unsafe { libc::pthread_cond_signal(self.c.get()) });
+    debug_assert_eq!(r, Ok(()));
+  }
+
+  pub fn notify_all(&self) {
+    let r = cvt(unsafe { libc::pthread_cond_broadcast(self.c.get()) });
+    debug_assert_eq!(r, Ok(()));
+  }
+}
+
+impl Drop for Condvar {
+  fn drop(&mut self) {
+    unsafe { libc::pthread_cond_destroy(self.c.get()) };
+  }
+}
+
+// Now + `dur` on CLOCK_MONOTONIC, saturating rather than overflowing.
+fn monotonic_deadline(dur: Duration) -> libc::timespec {
+  let mut now = MaybeUninit::<libc::timespec>::uninit();
+  let now = unsafe {
+    libc::clock_gettime(libc::CLOCK_MONOTONIC, now.as_mut_ptr());
+    now.assume_init()
+  };
+  let nsec = now.tv_nsec + dur.subsec_nanos() as libc::c_long;
+  let sec = libc::time_t::try_from(dur.as_secs())
+    .ok()
+    .and_then(|s| now.tv_sec.checked_add(s))
+    .and_then(|s| s.checked_add(nsec / 1_000_000_000));
+  match sec {
+    Some(sec) => libc::timespec {
+      tv_sec: sec,
+      tv_nsec: nsec % 1_000_000_000,
+    },
+    None => libc::timespec {
+      tv_sec: libc::time_t::MAX,
+      tv_nsec: 999_999_999,
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::primitive::mutex::Mutex;
+  use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::Instant,
+  };
+
+  // `lock::condvar`'s test, over the pthread types.
+  #[test]
+  fn test_condvar() {
+    let mutex = Mutex::new();
+    let value = AtomicU32::new(0);
+    let convar = Condvar::new();
+    let mut wakeups = 0;
+    thread::scope(|s| {
+      let start = Instant::now();
+      s.spawn(|| {
+        thread::sleep(Duration::from_secs(1));
+        let g = mutex.lock().unwrap();
+        value.store(123, Ordering::Relaxed);
+        drop(g);
+        convar.notify_one();
+        for _ in 0..1_000_000 {
+          convar.notify_one();
+        }
+      });
+      let mut guard = mutex.lock().unwrap();
+      while value.load(Ordering::Relaxed) < 100 {
+        guard = convar.wait(guard);
+        wakeups += 1;
+      }
+      dbg!(start.elapsed().as_millis())
+    });
+    dbg!(value.load(Ordering::Relaxed), wakeups);
+  }
+
+  #[test]
+  fn test_wait_timeout() {
+    let mutex = Mutex::new();
+    let convar = Condvar::new();
+    let start = Instant::now();
+    let (guard, result) = convar.wait_timeout(mutex.lock().unwrap(), Duration::from_millis(50));
+    assert!(result.timed_out());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    drop(guard);
+
+    let done = AtomicU32::new(0);
+    thread::scope(|s| {
+      s.spawn(|| {
+        thread::sleep(Duration::from_millis(50));
+        let _g = mutex.lock().unwrap();
+        done.store(1, Ordering::Relaxed);
+        convar.notify_one();
+      });
+      let mut guard = mutex.lock().unwrap();
+      while done.load(Ordering::Relaxed) == 0 {
+        let (g, result) = convar.wait_timeout(guard, Duration::from_secs(10));
+        assert!(!result.timed_out());
+        guard = g;
+      }
+    });
+
+    assert_eq!(monotonic_deadline(Duration::MAX).tv_sec, libc::time_t::MAX);
+  }
+
+  #[test]
+  #[should_panic(expected = "attempted to use a condition variable with two mutexes")]
+  fn test_two_mutexes() {
+    let a = Mutex::new();
+    let b = Mutex::new();
+    let convar = Condvar::new();
+    drop(convar.wait_timeout(a.lock().unwrap(), Duration::ZERO));
+    // Fine with the same mutex again.
+    drop(convar.wait_timeout(a.lock().unwrap(), Duration::ZERO));
+    drop(convar.wait_timeout(b.lock().unwrap(), Duration::ZERO));
+  }
+}
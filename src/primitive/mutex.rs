@@ -1,20 +1,302 @@
-use std::cell::UnsafeCell;
+use std::{
+  cell::UnsafeCell,
+  error, fmt,
+  marker::PhantomData,
+  mem::{ManuallyDrop, MaybeUninit},
+  ptr,
+  sync::atomic::{AtomicPtr, Ordering},
+};
 
-// pub struct Mutex {
-//   m: Box<UnsafeCell<libc::pthread_mutex_t>>,
-// }
+use crate::lock::raw::{GuardNoSend, RawMutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+  // Relocking from the owning thread deadlocks.
+  Normal,
+  // Relocking from the owning thread fails with `Error::Deadlock`.
+  ErrorCheck,
+  // The owning thread may lock it again, and must unlock as many times.
+  Recursive,
+}
+
+impl Kind {
+  fn raw(self) -> libc::c_int {
+    match self {
+      Kind::Normal => libc::PTHREAD_MUTEX_NORMAL,
+      Kind::ErrorCheck => libc::PTHREAD_MUTEX_ERRORCHECK,
+      Kind::Recursive => libc::PTHREAD_MUTEX_RECURSIVE,
+    }
+  }
+}
+
+// The error codes the pthread_mutex_* functions can return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+  // EBUSY: already locked, from `try_lock`.
+  WouldBlock,
+  // EDEADLK: an error checking mutex was already locked by this thread.
+  Deadlock,
+  // EPERM: not locked by this thread.
+  NotOwner,
+  // EAGAIN: a recursive mutex was locked too many times.
+  TooManyRecursions,
+  Os(libc::c_int),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::WouldBlock => "mutex is already locked".fmt(f),
+      Error::Deadlock => "mutex is already locked by this thread".fmt(f),
+      Error::NotOwner => "mutex is not locked by this thread".fmt(f),
+      Error::TooManyRecursions => "mutex was locked recursively too often".fmt(f),
+      Error::Os(e) => write!(f, "pthread mutex error {e}"),
+    }
+  }
+}
+
+impl error::Error for Error {}
+
+pub(crate) fn cvt(r: libc::c_int) -> Result<(), Error> {
+  match r {
+    0 => Ok(()),
+    libc::EBUSY => Err(Error::WouldBlock),
+    libc::EDEADLK => Err(Error::Deadlock),
+    libc::EPERM => Err(Error::NotOwner),
+    libc::EAGAIN => Err(Error::TooManyRecursions),
+    e => Err(Error::Os(e)),
+  }
+}
+
+// An OS mutex without data, as a reference to compare `lock::mutex::Mutex`
+// against. Boxed, because a pthread mutex must not move once initialized.
+pub struct Mutex {
+  pub(super) m: ManuallyDrop<Box<UnsafeCell<libc::pthread_mutex_t>>>,
+  kind: Kind,
+}
+
+unsafe impl Send for Mutex {}
+unsafe impl Sync for Mutex {}
+
+impl Mutex {
+  pub fn new() -> Self {
+    Self::with_kind(Kind::Normal)
+  }
+
+  pub fn with_kind(kind: Kind) -> Self {
+    let m = Box::new(UnsafeCell::new(libc::PTHREAD_MUTEX_INITIALIZER));
+    unsafe {
+      let mut attr = MaybeUninit::<libc::pthread_mutexattr_t>::uninit();
+      cvt(libc::pthread_mutexattr_init(attr.as_mut_ptr())).expect("pthread_mutexattr_init");
+      let r = cvt(libc::pthread_mutexattr_settype(
+        attr.as_mut_ptr(),
+        kind.raw(),
+      ))
+      .and_then(|_| cvt(libc::pthread_mutex_init(m.get(), attr.as_ptr())));
+      libc::pthread_mutexattr_destroy(attr.as_mut_ptr());
+      r.expect("pthread_mutex_init");
+    }
+    Self {
+      m: ManuallyDrop::new(m),
+      kind,
+    }
+  }
+
+  pub fn kind(&self) -> Kind {
+    self.kind
+  }
+
+  pub fn lock(&self) -> Result<MutexGuard<'_>, Error> {
+    cvt(unsafe { libc::pthread_mutex_lock(self.m.get()) })?;
+    Ok(MutexGuard {
+      mutex: self,
+      _marker: PhantomData,
+    })
+  }
+
+  pub fn try_lock(&self) -> Result<MutexGuard<'_>, Error> {
+    cvt(unsafe { libc::pthread_mutex_trylock(self.m.get()) })?;
+    Ok(MutexGuard {
+      mutex: self,
+      _marker: PhantomData,
+    })
+  }
+}
+
+impl Drop for Mutex {
+  fn drop(&mut self) {
+    // Fails with EBUSY if still locked, which can only happen if a guard
+    // was leaked. Then leak the mutex too, rather than free it while locked.
+    if unsafe { libc::pthread_mutex_destroy(self.m.get()) } == 0 {
+      unsafe { ManuallyDrop::drop(&mut self.m) };
+    }
+  }
+}
+
+// Must be dropped on the thread that locked it.
+pub struct MutexGuard<'a> {
+  pub(super) mutex: &'a Mutex,
+  _marker: PhantomData<*const ()>,
+}
+
+impl Drop for MutexGuard<'_> {
+  fn drop(&mut self) {
+    cvt(unsafe { libc::pthread_mutex_unlock(self.mutex.m.get()) }).expect("pthread_mutex_unlock");
+  }
+}
+
+// A pthread mutex must not be moved once used, so it lives in a box,
+// allocated on first use to keep `INIT` const (like std's LazyBox).
+pub struct RawPthreadMutex {
+  m: AtomicPtr<libc::pthread_mutex_t>,
+}
+
+unsafe impl Send for RawPthreadMutex {}
+unsafe impl Sync for RawPthreadMutex {}
+
+impl RawPthreadMutex {
+  fn get(&self) -> *mut libc::pthread_mutex_t {
+    let m = self.m.load(Ordering::Acquire);
+    if !m.is_null() {
+      return m;
+    }
+    let new = Box::into_raw(Box::new(libc::PTHREAD_MUTEX_INITIALIZER));
+    match self
+      .m
+      .compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire)
+    {
+      Ok(_) => new,
+      Err(m) => {
+        // Another thread won the race; ours was never used.
+        drop(unsafe { Box::from_raw(new) });
+        m
+      }
+    }
+  }
+}
+
+unsafe impl RawMutex for RawPthreadMutex {
+  const INIT: Self = Self {
+    m: AtomicPtr::new(ptr::null_mut()),
+  };
+
+  // pthread_mutex_unlock from another thread is undefined.
+  type GuardMarker = GuardNoSend;
+
+  // Checked in release builds too: carrying on after a failed lock would
+  // hand out a guard without mutual exclusion.
+  fn lock(&self) {
+    cvt(unsafe { libc::pthread_mutex_lock(self.get()) }).expect("pthread_mutex_lock");
+  }
+
+  fn try_lock(&self) -> bool {
+    unsafe { libc::pthread_mutex_trylock(self.get()) == 0 }
+  }
+
+  unsafe fn unlock(&self) {
+    cvt(unsafe { libc::pthread_mutex_unlock(self.get()) }).expect("pthread_mutex_unlock");
+  }
+}
+
+impl Drop for RawPthreadMutex {
+  fn drop(&mut self) {
+    let m = *self.m.get_mut();
+    // EBUSY if a guard was leaked: leak the box too, as `Mutex` does.
+    if !m.is_null() && unsafe { libc::pthread_mutex_destroy(m) } == 0 {
+      drop(unsafe { Box::from_raw(m) });
+    }
+  }
+}
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use std::{
+    sync::atomic::AtomicU64,
+    thread,
+    time::{Duration, Instant},
+  };
 
   #[test]
   fn test_mutex() {
-    unsafe {
-      libc::abs(6);
+    let m = Mutex::new();
+    let g = m.lock().unwrap();
+    assert_eq!(m.try_lock().err(), Some(Error::WouldBlock));
+    thread::scope(|s| {
+      s.spawn(|| {
+        assert_eq!(m.try_lock().err(), Some(Error::WouldBlock));
+        let start = Instant::now();
+        drop(m.lock().unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+      });
+      thread::sleep(Duration::from_millis(100));
+      drop(g);
+    });
+    drop(m.try_lock().unwrap());
+  }
+
+  #[test]
+  fn test_error_check() {
+    let m = Mutex::with_kind(Kind::ErrorCheck);
+    let g = m.lock().unwrap();
+    assert_eq!(m.lock().err(), Some(Error::Deadlock));
+    drop(g);
+    drop(m.lock().unwrap());
+  }
+
+  #[test]
+  fn test_recursive() {
+    let m = Mutex::with_kind(Kind::Recursive);
+    let g1 = m.lock().unwrap();
+    let g2 = m.lock().unwrap();
+    let g3 = m.try_lock().unwrap();
+    thread::scope(|s| {
+      s.spawn(|| assert_eq!(m.try_lock().err(), Some(Error::WouldBlock)));
+    });
+    drop((g3, g2, g1));
+    thread::scope(|s| {
+      s.spawn(|| drop(m.try_lock().unwrap()));
+    });
+  }
+
+  #[test]
+  fn test_leaked_guard() {
+    let m = Mutex::new();
+    std::mem::forget(m.lock().unwrap());
+    // Must not destroy the locked mutex.
+    drop(m);
+  }
+
+  #[test]
+  fn test_raw_leaked_guard() {
+    let m = crate::lock::raw::Mutex::<RawPthreadMutex, _>::new(0);
+    std::mem::forget(m.lock());
+    drop(m);
+  }
+
+  #[test]
+  fn pthread_mutex_benchmark() {
+    let m = Mutex::new();
+    let counter = AtomicU64::new(0);
+    let start = Instant::now();
+    for _ in 0..5_000_000 {
+      let _g = m.lock().unwrap();
+      counter.fetch_add(1, Ordering::Relaxed);
     }
-    // let m = Mutex::new();
-    // m.lock();
-    // m.unlock();
+    dbg!(start.elapsed());
+
+    let start = Instant::now();
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..5_000_000 {
+            let _g = m.lock().unwrap();
+            counter.fetch_add(1, Ordering::Relaxed);
+          }
+        });
+      }
+    });
+    assert_eq!(counter.load(Ordering::Relaxed), 25_000_000);
+    dbg!(start.elapsed());
   }
 }
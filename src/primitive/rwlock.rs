@@ -0,0 +1,199 @@
+use std::{
+  cell::UnsafeCell,
+  marker::PhantomData,
+  mem::MaybeUninit,
+  ops::{Deref, DerefMut},
+};
+
+use super::mutex::cvt;
+
+// glibc's PTHREAD_RWLOCK_PREFER_WRITER_NONRECURSIVE_NP, which libc lacks.
+// (Plain PREFER_WRITER_NP is ignored by glibc and prefers readers.)
+const PREFER_WRITER_NONRECURSIVE: libc::c_int = 2;
+
+// The OS counterpart of `lock::rwlock::RwLock`. Writer-preferring:
+// once a writer waits, new readers block, so a thread must not take
+// a read lock it already holds again.
+pub struct RwLock<T> {
+  l: Box<UnsafeCell<libc::pthread_rwlock_t>>,
+  val: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for RwLock<T> where T: Send {}
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+// Both guards must be dropped on the thread that locked, as for
+// `primitive::mutex::MutexGuard`.
+pub struct ReadGuard<'a, T> {
+  lock: &'a RwLock<T>,
+  _marker: PhantomData<*const ()>,
+}
+
+unsafe impl<T: Sync> Sync for ReadGuard<'_, T> {}
+
+impl<T> Deref for ReadGuard<'_, T> {
+  type Target = T;
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.lock.val.get() }
+  }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+  fn drop(&mut self) {
+    self.lock.unlock();
+  }
+}
+
+pub struct WriteGuard<'a, T> {
+  lock: &'a RwLock<T>,
+  _marker: PhantomData<*const ()>,
+}
+
+unsafe impl<T: Sync> Sync for WriteGuard<'_, T> {}
+
+impl<T> Deref for WriteGuard<'_, T> {
+  type Target = T;
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.lock.val.get() }
+  }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    unsafe { &mut *self.lock.val.get() }
+  }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+  fn drop(&mut self) {
+    self.lock.unlock();
+  }
+}
+
+impl<T> RwLock<T> {
+  pub fn new(val: T) -> Self {
+    let l = Box::new(UnsafeCell::new(libc::PTHREAD_RWLOCK_INITIALIZER));
+    unsafe {
+      let mut attr = MaybeUninit::<libc::pthread_rwlockattr_t>::uninit();
+      cvt(libc::pthread_rwlockattr_init(attr.as_mut_ptr())).expect("pthread_rwlockattr_init");
+      let r = cvt(libc::pthread_rwlockattr_setkind_np(
+        attr.as_mut_ptr(),
+        PREFER_WRITER_NONRECURSIVE,
+      ))
+      .and_then(|_| cvt(libc::pthread_rwlock_init(l.get(), attr.as_ptr())));
+      libc::pthread_rwlockattr_destroy(attr.as_mut_ptr());
+      r.expect("pthread_rwlock_init");
+    }
+    Self {
+      l,
+      val: UnsafeCell::new(val),
+    }
+  }
+
+  // Panics on EAGAIN (too many readers) or EDEADLK (write-locked by us),
+  // like `lock::rwlock::RwLock::read` does on too many readers.
+  pub fn read(&self) -> ReadGuard<'_, T> {
+    if let Err(e) = cvt(unsafe { libc::pthread_rwlock_rdlock(self.l.get()) }) {
+      panic!("pthread_rwlock_rdlock: {e}");
+    }
+    ReadGuard {
+      lock: self,
+      _marker: PhantomData,
+    }
+  }
+
+  pub fn write(&self) -> WriteGuard<'_, T> {
+    if let Err(e) = cvt(unsafe { libc::pthread_rwlock_wrlock(self.l.get()) }) {
+      panic!("pthread_rwlock_wrlock: {e}");
+    }
+    WriteGuard {
+      lock: self,
+      _marker: PhantomData,
+    }
+  }
+
+  fn unlock(&self) {
+    let r = cvt(unsafe { libc::pthread_rwlock_unlock(self.l.get()) });
+    debug_assert_eq!(r, Ok(()));
+  }
+}
+
+impl<T> Drop for RwLock<T> {
+  fn drop(&mut self) {
+    unsafe { libc::pthread_rwlock_destroy(self.l.get()) };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+  };
+
+  #[test]
+  fn test_rwlock() {
+    let l = RwLock::new(Vec::new());
+    thread::scope(|s| {
+      for i in 0..4 {
+        let l = &l;
+        s.spawn(move || {
+          for j in 0..1000 {
+            l.write().push(i * 1000 + j);
+            assert!(l.read().len() <= 4000);
+          }
+        });
+      }
+    });
+    let mut v = l.read().clone();
+    v.sort();
+    assert_eq!(v, (0..4000).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn test_writer_preferred() {
+    let l = RwLock::new(0);
+    let stop = AtomicBool::new(false);
+    thread::scope(|s| {
+      // Overlapping readers, so the lock is never free of them.
+      for _ in 0..4 {
+        s.spawn(|| {
+          while !stop.load(Ordering::Relaxed) {
+            let _g = l.read();
+            thread::sleep(Duration::from_millis(1));
+          }
+        });
+      }
+      thread::sleep(Duration::from_millis(50));
+      let start = Instant::now();
+      *l.write() += 1;
+      stop.store(true, Ordering::Relaxed);
+      assert!(start.elapsed() < Duration::from_secs(1));
+    });
+  }
+
+  // Same workload as for `lock::rwlock::RwLock` in `lock::rwlock`'s tests.
+  #[test]
+  fn rwlock_benchmark() {
+    let l = RwLock::new(0u64);
+    let start = Instant::now();
+    thread::scope(|s| {
+      for i in 0..4 {
+        let l = &l;
+        s.spawn(move || {
+          for j in 0..1_000_000 {
+            if (i + j) % 100 == 0 {
+              *l.write() += 1;
+            } else {
+              std::hint::black_box(*l.read());
+            }
+          }
+        });
+      }
+    });
+    assert_eq!(*l.read(), 40_000);
+    dbg!(start.elapsed());
+  }
+}